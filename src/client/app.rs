@@ -1,59 +1,80 @@
 use crate::{
-    client::error::Result,
-    client::ui,
-    data::{self, CanvasColor, Coord, Line, Message},
-    message::{InitialState, ToClientMsg, ToServerMsg},
-    server::skribbl::{PlayerState, SkribblState},
+    client::error::{Error, Result},
+    client::{export, ui},
+    data::{
+        self, CanvasColor, CanvasEdit, CanvasOp, Coord, Fill, Line, Message, PlayerListEntry,
+        ReplayFrame, Shape, ShapeKind, MAX_LINE_THICKNESS,
+    },
+    message::{
+        decode_msg, DecodeError, InitialState, JoinRequest, ToClientMsg, ToServerMsg,
+        PROTOCOL_VERSION,
+    },
+    render,
+    server::{
+        lobby::LobbyState,
+        skribbl::{PlayerState, SkribblState},
+    },
     ClientEvent,
 };
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent};
 use futures_util::sink::SinkExt;
 use futures_util::stream::StreamExt;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use data::{CommandMsg, Username};
 use tokio_tungstenite::WebSocketStream;
 use tui::{backend::Backend, Terminal};
 
-const PALETTE: [CanvasColor; 16] = [
-    CanvasColor::White,
-    CanvasColor::Gray,
-    CanvasColor::DarkGray,
-    CanvasColor::Black,
-    CanvasColor::Red,
-    CanvasColor::LightRed,
-    CanvasColor::Green,
-    CanvasColor::LightGreen,
-    CanvasColor::Blue,
-    CanvasColor::LightBlue,
-    CanvasColor::Yellow,
-    CanvasColor::LightYellow,
-    CanvasColor::Cyan,
-    CanvasColor::LightCyan,
-    CanvasColor::Magenta,
-    CanvasColor::LightMagenta,
-];
+/// how often the server is reminded that we're still typing
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub struct AppCanvas {
     pub palette: Vec<CanvasColor>,
-    pub lines: Vec<data::Line>,
+    pub ops: Vec<CanvasOp>,
     pub dimensions: (usize, usize),
 }
 
 impl AppCanvas {
-    fn new(dimensions: (usize, usize), lines: Vec<data::Line>) -> Self {
+    fn new(dimensions: (usize, usize), ops: Vec<CanvasOp>, palette: Vec<CanvasColor>) -> Self {
         AppCanvas {
-            lines,
+            ops,
             dimensions,
-            palette: PALETTE.to_vec(),
+            palette,
         }
     }
 }
 
 impl AppCanvas {
     pub fn draw_line(&mut self, line: Line) {
-        self.lines.push(line);
+        self.ops.push(CanvasOp::Line(line));
+    }
+
+    pub fn fill(&mut self, fill: Fill) {
+        self.ops.push(CanvasOp::Fill(fill));
     }
+
+    pub fn draw_shape(&mut self, shape: Shape) {
+        self.ops.push(CanvasOp::Shape(shape));
+    }
+
+    /// the color of every cell, indexed by [y][x]. Cells nothing was drawn on are None.
+    pub fn rasterize(&self) -> Vec<Vec<Option<CanvasColor>>> {
+        render::rasterize(&self.ops, self.dimensions)
+    }
+}
+
+/// a drawing the server is playing back. It's shown instead of the canvas until it's over
+#[derive(Debug)]
+pub struct Replay {
+    pub canvas: AppCanvas,
+    /// the frames received so far
+    frames: Vec<ReplayFrame>,
+    /// where to save the frames to once the replay is over
+    file: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -69,20 +90,77 @@ pub struct App {
     pub session: ServerSession,
     pub last_mouse_pos: Option<Coord>,
     pub current_color: CanvasColor,
+    pub current_thickness: u8,
+    /// the shape dragging the mouse draws. Without one, it draws freehand
+    pub current_shape: Option<ShapeKind>,
     pub game_state: Option<SkribblState>,
+    pub lobby_state: Option<LobbyState>,
+    pub scoreboard: Vec<(Username, u32)>,
+    /// how many spectators are watching the room
+    pub spectator_count: usize,
+    /// everyone in the room, ordered by name
+    pub players: Vec<PlayerListEntry>,
+    /// the other users that are typing a message
+    pub typing_users: Vec<Username>,
+    /// when we last told the server we're typing, while we are
+    typing_sent_at: Option<Instant>,
     pub remaining_time: Option<u32>,
+    /// when the current turn ends on our clock, while the game isn't paused
+    pub round_end: Option<Instant>,
+    pub word_choices: Vec<String>,
+    /// how many players guessed the word this turn, and how many may guess it
+    pub guess_progress: Option<(usize, usize)>,
+    pub replay: Option<Replay>,
 }
 
 impl App {
     pub fn new(session: ServerSession, initial_state: InitialState) -> App {
         App {
-            canvas: AppCanvas::new(initial_state.dimensions, initial_state.lines),
-            chat: Chat::default(),
+            canvas: AppCanvas::new(
+                initial_state.dimensions,
+                initial_state.canvas_ops,
+                initial_state.palette,
+            ),
+            chat: Chat {
+                messages: initial_state
+                    .chat_history
+                    .into_iter()
+                    .map(|msg| msg.message)
+                    .collect(),
+                ..Chat::default()
+            },
             last_mouse_pos: None,
-            current_color: CanvasColor::White,
+            current_shape: None,
+            current_color: initial_state.color,
+            current_thickness: 1,
+            scoreboard: initial_state
+                .skribbl_state
+                .as_ref()
+                .map(|state| state.scoreboard())
+                .unwrap_or_default(),
             game_state: initial_state.skribbl_state,
+            lobby_state: initial_state.lobby_state,
             session,
+            spectator_count: 0,
+            players: Vec::new(),
+            typing_users: Vec::new(),
+            typing_sent_at: None,
             remaining_time: None,
+            round_end: None,
+            word_choices: Vec::new(),
+            guess_progress: None,
+            replay: None,
+        }
+    }
+
+    /// the seconds left in the current turn, counting down between the server's ticks
+    pub fn remaining_secs(&self) -> u32 {
+        match self.round_end {
+            Some(round_end) => {
+                let remaining = round_end.saturating_duration_since(Instant::now());
+                remaining.as_secs_f64().ceil() as u32
+            }
+            None => self.remaining_time.unwrap_or(0),
         }
     }
 
@@ -93,6 +171,9 @@ impl App {
     }
 
     pub fn is_drawing(&self) -> bool {
+        if self.session.spectator {
+            return false;
+        }
         self.game_state
             .as_ref()
             .map(|x| x.is_drawing(&self.session.username))
@@ -106,6 +187,15 @@ impl App {
 
         let dimensions = self.canvas.dimensions;
         match evt {
+            MouseEvent::Down(MouseButton::Right, x, y, _) => {
+                let fill = Fill {
+                    x,
+                    y,
+                    color: self.current_color,
+                };
+                self.canvas.fill(fill);
+                self.session.send(ToServerMsg::Fill(fill)).await?;
+            }
             MouseEvent::Down(_, x, y, _) => {
                 if y == 0 {
                     let swatch_size = dimensions.0 / self.canvas.palette.len() as usize;
@@ -118,25 +208,56 @@ impl App {
                     self.last_mouse_pos = Some(Coord(x, y));
                 }
             }
-            MouseEvent::Up(_, _, _, _) => {
+            MouseEvent::Up(_, x, y, _) => {
+                if let (Some(kind), Some(start)) = (self.current_shape, self.last_mouse_pos) {
+                    let shape = Shape {
+                        kind,
+                        start,
+                        end: Coord(x, y),
+                        color: self.current_color,
+                        thickness: self.current_thickness,
+                    };
+                    self.canvas.draw_shape(shape);
+                    self.session.send(ToServerMsg::Shape(shape)).await?;
+                }
                 self.last_mouse_pos = None;
             }
+            MouseEvent::ScrollUp(_, _, _) => {
+                self.current_thickness = (self.current_thickness + 1).min(MAX_LINE_THICKNESS);
+            }
+            MouseEvent::ScrollDown(_, _, _) => {
+                self.current_thickness = self.current_thickness.saturating_sub(1).max(1);
+            }
+            // shapes are drawn once the mouse is released
+            MouseEvent::Drag(..) if self.current_shape.is_some() => {}
             MouseEvent::Drag(_, x, y, _) => {
                 let mouse_pos = Coord(x, y);
                 let line = Line::new(
                     self.last_mouse_pos.unwrap_or(mouse_pos),
                     mouse_pos,
                     self.current_color,
+                    self.current_thickness,
                 );
                 self.canvas.draw_line(line);
                 self.session.send(ToServerMsg::NewLine(line)).await?;
                 self.last_mouse_pos = Some(mouse_pos);
             }
-            _ => {}
         }
         Ok(())
     }
 
+    /// if we are choosing a word, returns the word the input refers to, either by its number or by name
+    fn chosen_word(&self, input: &str) -> Option<String> {
+        let input = input.trim();
+        input
+            .parse::<usize>()
+            .ok()
+            .and_then(|idx| idx.checked_sub(1))
+            .and_then(|idx| self.word_choices.get(idx))
+            .or_else(|| self.word_choices.iter().find(|word| *word == input))
+            .cloned()
+    }
+
     pub async fn handle_chat_key_event(&mut self, event: &KeyEvent) -> Result<()> {
         let KeyEvent { modifiers, code } = event;
         match code {
@@ -147,12 +268,115 @@ impl App {
                 }
 
                 let msg_content = self.chat.input.clone();
-                if msg_content.starts_with("!") {
+                if self.session.spectator {
+                    self.chat
+                        .messages
+                        .push(Message::SystemMsg("Spectators can't chat".to_string()));
+                } else if let Some(word) = self.chosen_word(&msg_content) {
+                    self.word_choices.clear();
+                    self.session.send(ToServerMsg::WordChosen(word)).await?;
+                } else if msg_content.starts_with("!") {
                     if msg_content.starts_with("!kick ") {
                         let msg_without_cmd =
                             msg_content.trim_start_matches("!kick ").trim().to_string();
                         let command = CommandMsg::KickPlayer(Username::from(msg_without_cmd));
                         self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!ban ") {
+                        let player = msg_content.trim_start_matches("!ban ").trim();
+                        let command = CommandMsg::BanPlayer(Username::from(player.to_string()));
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!unban ") {
+                        let player = msg_content.trim_start_matches("!unban ").trim();
+                        let command = CommandMsg::UnbanPlayer(Username::from(player.to_string()));
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!mute ") {
+                        let player = msg_content.trim_start_matches("!mute ").trim();
+                        let command = CommandMsg::MutePlayer(Username::from(player.to_string()));
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!unmute ") {
+                        let player = msg_content.trim_start_matches("!unmute ").trim();
+                        let command = CommandMsg::UnmutePlayer(Username::from(player.to_string()));
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!votekick ") {
+                        let target = msg_content.trim_start_matches("!votekick ").trim();
+                        let command = CommandMsg::VoteKick(Username::from(target.to_string()));
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!ready" {
+                        self.session.send(ToServerMsg::Ready).await?;
+                    } else if msg_content.trim() == "!start" {
+                        let command = CommandMsg::StartGame;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!w ") {
+                        match msg_content.trim_start_matches("!w ").trim().split_once(' ') {
+                            Some((to, text)) => {
+                                let whisper = ToServerMsg::Whisper {
+                                    to: Username::from(to.to_string()),
+                                    text: text.trim().to_string(),
+                                };
+                                self.session.send(whisper).await?;
+                            }
+                            None => self.chat.messages.push(Message::SystemMsg(
+                                "Usage: !w <username> <message>".to_string(),
+                            )),
+                        }
+                    } else if msg_content.trim() == "!skip" {
+                        let command = CommandMsg::VoteSkip;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!setword ") {
+                        let word = msg_content.trim_start_matches("!setword ").trim();
+                        let command = CommandMsg::SetWord(word.to_string());
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!addword ") {
+                        let word = msg_content.trim_start_matches("!addword ").trim();
+                        let command = CommandMsg::AddWord(word.to_string());
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!removeword ") {
+                        let word = msg_content.trim_start_matches("!removeword ").trim();
+                        let command = CommandMsg::RemoveWord(word.to_string());
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!reloadwords" {
+                        let command = CommandMsg::ReloadWords;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!stop" {
+                        let command = CommandMsg::StopGame;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!restart" {
+                        let command = CommandMsg::RestartGame;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!resetscores" {
+                        let command = CommandMsg::ResetScores;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!pause" {
+                        let command = CommandMsg::TogglePause;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.starts_with("!replay") {
+                        let file = msg_content.trim_start_matches("!replay").trim();
+                        self.replay = Some(Replay {
+                            canvas: AppCanvas::new(
+                                self.canvas.dimensions,
+                                Vec::new(),
+                                self.canvas.palette.clone(),
+                            ),
+                            frames: Vec::new(),
+                            file: Some(PathBuf::from(file)).filter(|_| !file.is_empty()),
+                        });
+                        let command = CommandMsg::ReplayDrawing;
+                        self.session.send(ToServerMsg::CommandMsg(command)).await?;
+                    } else if msg_content.trim() == "!shape" {
+                        self.current_shape = None;
+                    } else if msg_content.starts_with("!shape ") {
+                        let kind = msg_content.trim_start_matches("!shape ").trim();
+                        match kind.parse::<ShapeKind>() {
+                            Ok(kind) => self.current_shape = Some(kind),
+                            Err(err) => self.chat.messages.push(Message::SystemMsg(err)),
+                        }
+                    } else if msg_content.starts_with("!save ") {
+                        let path = msg_content.trim_start_matches("!save ").trim();
+                        let msg = match export::save(&self.canvas, Path::new(path)) {
+                            Ok(()) => format!("Saved the drawing to {}", path),
+                            Err(err) => format!("Could not save the drawing: {}", err),
+                        };
+                        self.chat.messages.push(Message::SystemMsg(msg));
                     };
                 } else {
                     let message =
@@ -167,10 +391,17 @@ impl App {
             KeyCode::Char('h') if modifiers.contains(KeyModifiers::CONTROL) => {
                 self.chat.input.pop();
             }
+            KeyCode::Char('z') if modifiers.contains(KeyModifiers::CONTROL) => {
+                if !self.is_drawing() {
+                    return Ok(());
+                }
+                // the line is removed once the server confirms the undo
+                self.session.send(ToServerMsg::UndoLine).await?;
+            }
             KeyCode::Delete => {
                 if self.is_drawing() {
                     self.session.send(ToServerMsg::ClearCanvas).await?;
-                    self.canvas.lines.clear();
+                    self.canvas.ops.clear();
                 }
             }
             KeyCode::Char(c) => {
@@ -178,6 +409,25 @@ impl App {
             }
             _ => {}
         }
+        self.update_typing().await
+    }
+
+    /// tells the server whether we're typing a message, repeating it while we keep typing
+    async fn update_typing(&mut self) -> Result<()> {
+        if self.session.spectator {
+            return Ok(());
+        }
+        if self.chat.input.is_empty() {
+            if self.typing_sent_at.take().is_some() {
+                self.session.send(ToServerMsg::Typing(false)).await?;
+            }
+        } else if self
+            .typing_sent_at
+            .is_none_or(|sent_at| sent_at.elapsed() >= TYPING_INTERVAL)
+        {
+            self.typing_sent_at = Some(Instant::now());
+            self.session.send(ToServerMsg::Typing(true)).await?;
+        }
         Ok(())
     }
 
@@ -190,24 +440,199 @@ impl App {
                 self.handle_mouse_event(mouse_evt).await?;
             }
             ClientEvent::ServerMessage(m) => match m {
-                ToClientMsg::TimeChanged(new_time) => {
-                    self.remaining_time = Some(new_time);
+                ToClientMsg::TimeChanged {
+                    remaining_secs,
+                    server_time,
+                    round_end,
+                } => {
+                    self.remaining_time = Some(remaining_secs);
+                    self.round_end = round_end.map(|round_end| {
+                        Instant::now()
+                            + Duration::from_millis(round_end.saturating_sub(server_time))
+                    });
                 }
-                ToClientMsg::NewMessage(message) => self.chat.messages.push(message),
+                ToClientMsg::NewMessage(msg) => self.chat.messages.push(msg.message),
                 ToClientMsg::NewLine(line) => {
                     self.canvas.draw_line(line);
                 }
+                ToClientMsg::NewLines(lines) => {
+                    for line in lines {
+                        self.canvas.draw_line(line);
+                    }
+                }
+                ToClientMsg::Fill(fill) => {
+                    self.canvas.fill(fill);
+                }
+                ToClientMsg::Shape(shape) => {
+                    self.canvas.draw_shape(shape);
+                }
                 ToClientMsg::SkribblStateChanged(new_state) => {
+                    if !new_state.is_choosing_word() {
+                        self.word_choices.clear();
+                    }
+                    // a new turn, or a new word for the current one
+                    if new_state.player_states.values().all(|x| !x.has_solved) {
+                        self.guess_progress = None;
+                    }
                     self.game_state = Some(new_state);
+                    self.lobby_state = None;
+                }
+                ToClientMsg::ServerShutdown => {
+                    self.chat.messages.push(Message::SystemMsg(
+                        "The server is shutting down".to_string(),
+                    ));
+                }
+                ToClientMsg::ReconnectToken(token) => {
+                    self.chat.messages.push(Message::SystemMsg(format!(
+                        "If your connection drops, rejoin soon with --reconnect-token {} to keep your score",
+                        token
+                    )));
+                }
+                ToClientMsg::SpectatorCount(spectator_count) => {
+                    self.spectator_count = spectator_count;
+                }
+                ToClientMsg::PlayerList(players) => {
+                    self.players = players;
+                }
+                ToClientMsg::UserTyping(username, typing) => {
+                    self.typing_users.retain(|user| *user != username);
+                    if typing {
+                        self.typing_users.push(username);
+                    }
+                }
+                ToClientMsg::Scoreboard(scoreboard) => {
+                    self.scoreboard = scoreboard;
+                }
+                ToClientMsg::LobbyStateChanged(lobby_state) => {
+                    self.lobby_state = Some(lobby_state);
+                }
+                ToClientMsg::ChooseWord(choices) => {
+                    let options = choices
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, word)| format!("{}) {}", idx + 1, word))
+                        .collect::<Vec<_>>()
+                        .join("  ");
+                    self.chat.messages.push(Message::SystemMsg(format!(
+                        "Choose a word by typing its number: {}",
+                        options
+                    )));
+                    self.word_choices = choices;
+                }
+                ToClientMsg::GuessProgress { solved, total } => {
+                    self.guess_progress = Some((solved, total));
+                }
+                ToClientMsg::NextDrawer(username) => {
+                    self.guess_progress = None;
+                    self.chat
+                        .messages
+                        .push(Message::SystemMsg(format!("{} draws next", username)));
+                }
+                ToClientMsg::RoundStarted {
+                    round,
+                    drawer,
+                    word,
+                } => {
+                    let letters = word.chars().map(String::from).collect::<Vec<_>>();
+                    self.chat.messages.push(Message::SystemMsg(format!(
+                        "Round {} \u{2014} {} is drawing: {}",
+                        round,
+                        drawer,
+                        letters.join(" ")
+                    )));
+                }
+                ToClientMsg::YourTurn { word } => {
+                    self.chat.messages.push(Message::SystemMsg(format!(
+                        "It's your turn! Draw \"{}\"",
+                        word
+                    )));
+                }
+                ToClientMsg::WordHint(hint) => {
+                    if let Some(state) = self.game_state.as_mut() {
+                        state.set_current_word(hint);
+                    }
+                }
+                ToClientMsg::UndoLine => {
+                    self.canvas.ops.pop();
+                }
+                ToClientMsg::CanvasOpRejected(op) => {
+                    // others may have drawn since, so only the rejected op is taken back
+                    if let Some(idx) = self.canvas.ops.iter().rposition(|drawn| *drawn == op) {
+                        self.canvas.ops.remove(idx);
+                    }
+                }
+                ToClientMsg::ReplayFrame(frame) => {
+                    if let Some(replay) = self.replay.as_mut() {
+                        match frame.edit {
+                            CanvasEdit::Op(op) => replay.canvas.ops.push(op),
+                            CanvasEdit::Undo => {
+                                replay.canvas.ops.pop();
+                            }
+                        }
+                        replay.frames.push(frame);
+                    }
+                }
+                ToClientMsg::ReplayEnded => {
+                    if let Some(Replay {
+                        frames,
+                        file: Some(file),
+                        ..
+                    }) = self.replay.take()
+                    {
+                        let result = serde_json::to_string(&frames)
+                            .map_err(std::io::Error::from)
+                            .and_then(|json| std::fs::write(&file, json));
+                        let msg = match result {
+                            Ok(()) => format!("Saved the replay to {}", file.display()),
+                            Err(err) => format!("Could not save the replay: {}", err),
+                        };
+                        self.chat.messages.push(Message::SystemMsg(msg));
+                    }
                 }
                 ToClientMsg::ClearCanvas => {
-                    self.canvas.lines.clear();
+                    self.canvas.ops.clear();
+                }
+                ToClientMsg::DimensionsChanged {
+                    dimensions,
+                    canvas_ops,
+                } => {
+                    self.canvas.dimensions = dimensions;
+                    self.canvas.ops = canvas_ops;
+                }
+                ToClientMsg::Kicked(reason) => {
+                    self.chat
+                        .messages
+                        .push(Message::SystemMsg(format!("{}. Press Esc to quit", reason)));
                 }
-                ToClientMsg::GameOver(state) => {
-                    dbg!(state);
-                    panic!("Game over, I couldn't yet be bothered to implement this in a better way yet,...");
+                ToClientMsg::GameStopped => {
+                    self.game_state = None;
+                    self.lobby_state = None;
+                    self.scoreboard.clear();
+                    self.remaining_time = None;
+                    self.round_end = None;
+                    self.word_choices.clear();
+                    self.guess_progress = None;
+                }
+                ToClientMsg::GameOver { scores } => {
+                    self.game_state = None;
+                    self.scoreboard.clear();
+                    self.remaining_time = None;
+                    self.round_end = None;
+                    self.word_choices.clear();
+                    self.guess_progress = None;
+                    self.chat
+                        .messages
+                        .push(Message::SystemMsg("Game over! Final scores:".to_string()));
+                    for (place, (username, score)) in scores.into_iter().enumerate() {
+                        self.chat.messages.push(Message::SystemMsg(format!(
+                            "{}. {}: {}",
+                            place + 1,
+                            username,
+                            score
+                        )));
+                    }
                 }
-                ToClientMsg::InitialState(_) => {}
+                ToClientMsg::InitialState(_) | ToClientMsg::JoinRejected(_) => {}
             },
         }
         Ok(())
@@ -233,14 +658,18 @@ impl App {
 pub struct ServerSession {
     to_server_send: tokio::sync::mpsc::Sender<ToServerMsg>,
     pub username: Username,
+    pub spectator: bool,
 }
 
 impl ServerSession {
     pub async fn establish_connection(
         addr: &str,
-        username: Username,
+        join_request: JoinRequest,
         mut evt_send: tokio::sync::mpsc::Sender<ClientEvent>,
     ) -> Result<App> {
+        let username = Username::from(join_request.username.clone());
+        let codec = join_request.codec;
+        let spectator = join_request.spectator;
         let (to_server_send, mut to_server_recv) = tokio::sync::mpsc::channel::<ToServerMsg>(1);
 
         let ws: WebSocketStream<_> = tokio_tungstenite::connect_async(addr)
@@ -249,18 +678,30 @@ impl ServerSession {
             .0;
         let (mut ws_send, mut ws_recv) = ws.split();
 
-        // first send the username to the server
+        // first send the username and room to the server
         ws_send
-            .send(tungstenite::Message::Text(username.clone().into()))
+            .send(tungstenite::Message::Text(
+                serde_json::to_string(&join_request).unwrap(),
+            ))
             .await
             .unwrap();
 
         // and wait for the initial state
         let initial_state: InitialState = loop {
             let msg = ws_recv.next().await;
-            if let Some(Ok(tungstenite::Message::Text(msg))) = msg {
-                if let Ok(ToClientMsg::InitialState(state)) = serde_json::from_str(&msg) {
-                    break state;
+            if let Some(Ok(msg)) = msg {
+                match decode_msg(&msg) {
+                    Some(Ok(ToClientMsg::InitialState(state))) => break state,
+                    Some(Ok(ToClientMsg::JoinRejected(reason))) => {
+                        return Err(Error::JoinRejected(reason))
+                    }
+                    Some(Err(DecodeError::VersionMismatch(version))) => {
+                        return Err(Error::JoinRejected(format!(
+                            "the server uses protocol version {}, but we use {}",
+                            version, PROTOCOL_VERSION
+                        )))
+                    }
+                    _ => {}
                 }
             }
         };
@@ -269,8 +710,11 @@ impl ServerSession {
         let send_handle = tokio::spawn(async move {
             loop {
                 let msg = to_server_recv.recv().await;
-                let msg = serde_json::to_string(&msg).unwrap();
-                if let Err(_) = ws_send.send(tungstenite::Message::Text(msg)).await {
+                let msg = match codec.encode(&msg) {
+                    Ok(msg) => msg,
+                    Err(_) => continue,
+                };
+                if ws_send.send(msg).await.is_err() {
                     break;
                 }
             }
@@ -280,13 +724,15 @@ impl ServerSession {
         tokio::spawn(async move {
             loop {
                 match ws_recv.next().await {
-                    Some(Ok(tungstenite::Message::Text(msg))) => {
-                        let msg = serde_json::from_str(&msg).unwrap();
-                        let _ = evt_send.send(ClientEvent::ServerMessage(msg)).await;
-                    }
                     Some(Ok(tungstenite::Message::Close(_))) => {
                         break;
                     }
+                    Some(Ok(msg)) => {
+                        if let Some(msg) = decode_msg(&msg) {
+                            let msg = msg.expect("Could not decode msg");
+                            let _ = evt_send.send(ClientEvent::ServerMessage(msg)).await;
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -297,6 +743,7 @@ impl ServerSession {
             ServerSession {
                 to_server_send,
                 username,
+                spectator,
             },
             initial_state,
         ))
@@ -6,6 +6,7 @@ pub enum Error {
     CrosstermError(crossterm::ErrorKind),
     IOError(std::io::Error),
     WebSocketError(tungstenite::error::Error),
+    JoinRejected(String),
 }
 
 impl<T> From<tokio::sync::mpsc::error::SendError<T>> for Error {
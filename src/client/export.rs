@@ -0,0 +1,123 @@
+use super::app::AppCanvas;
+use crate::{
+    data::{CanvasColor, CanvasOp, Coord, Line},
+    render::{self, paint, BACKGROUND, CELL_HEIGHT, CELL_WIDTH},
+};
+use image::ImageResult;
+use std::{fmt::Write, path::Path};
+
+/// saves the canvas as an SVG image if the file name ends in .svg, and as a PNG image otherwise
+pub fn save(canvas: &AppCanvas, path: &Path) -> Result<(), String> {
+    if path.extension().is_some_and(|ext| ext == "svg") {
+        let svg = to_svg(canvas)?;
+        std::fs::write(path, svg).map_err(|err| err.to_string())
+    } else {
+        save_png(canvas, path).map_err(|err| err.to_string())
+    }
+}
+
+/// writes the canvas as a PNG image. An empty canvas gives a blank image of the canvas' size
+pub fn save_png(canvas: &AppCanvas, path: &Path) -> ImageResult<()> {
+    render::to_image(&canvas.ops, canvas.dimensions).save_with_format(path, image::ImageFormat::Png)
+}
+
+/// renders the canvas as an SVG document, one canvas cell per unit. Connected lines of the same
+/// color and thickness become one polyline, and fills become the cells they colored.
+/// Lines that don't fit on the canvas are left out
+pub fn to_svg(canvas: &AppCanvas) -> Result<String, String> {
+    if canvas.ops.is_empty() {
+        return Err("there is nothing drawn yet".to_string());
+    }
+    let (width, height) = canvas.dimensions;
+    let is_on_canvas = |Coord(x, y): Coord| (x as usize) < width && (y as usize) < height;
+
+    // writing to a String can't fail, so the results of writeln! are unwrapped
+    let mut svg = String::new();
+    // stretching the units to terminal cells keeps the picture's proportions
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" preserveAspectRatio="none">"#,
+        width as u32 * CELL_WIDTH,
+        height as u32 * CELL_HEIGHT,
+        width,
+        height
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="{}"/>"#,
+        width,
+        height,
+        svg_color(BACKGROUND.0)
+    )
+    .unwrap();
+
+    // fills need the cells drawn before them, so the canvas is replayed along the way
+    let mut cells = vec![vec![None; width]; height];
+    let mut polyline: Option<(Line, Vec<Coord>)> = None;
+    for op in canvas.ops.iter() {
+        let painted = paint(&mut cells, op);
+        match op {
+            CanvasOp::Line(line) if is_on_canvas(line.start) && is_on_canvas(line.end) => {
+                match &mut polyline {
+                    Some((style, points))
+                        if style.color == line.color
+                            && style.thickness == line.thickness
+                            && points.last() == Some(&line.start) =>
+                    {
+                        points.push(line.end)
+                    }
+                    _ => {
+                        write_polyline(&mut svg, polyline.take());
+                        polyline = Some((*line, vec![line.start, line.end]));
+                    }
+                }
+            }
+            CanvasOp::Line(_) => {}
+            CanvasOp::Fill(fill) => {
+                write_polyline(&mut svg, polyline.take());
+                write_cells(&mut svg, fill.color, &painted);
+            }
+            CanvasOp::Shape(shape) => {
+                write_polyline(&mut svg, polyline.take());
+                write_cells(&mut svg, shape.color, &painted);
+            }
+        }
+    }
+    write_polyline(&mut svg, polyline);
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+fn write_polyline(svg: &mut String, polyline: Option<(Line, Vec<Coord>)>) {
+    if let Some((style, points)) = polyline {
+        let points = points
+            .iter()
+            .map(|Coord(x, y)| format!("{},{}", *x as f32 + 0.5, *y as f32 + 0.5))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="{}" stroke-linecap="square" stroke-linejoin="round"/>"#,
+            points,
+            svg_color(style.color.rgb()),
+            style.thickness
+        )
+        .unwrap();
+    }
+}
+
+fn write_cells(svg: &mut String, color: CanvasColor, cells: &[(usize, usize)]) {
+    if cells.is_empty() {
+        return;
+    }
+    writeln!(svg, r#"<g fill="{}">"#, svg_color(color.rgb())).unwrap();
+    for (x, y) in cells {
+        writeln!(svg, r#"<rect x="{}" y="{}" width="1" height="1"/>"#, x, y).unwrap();
+    }
+    svg.push_str("</g>\n");
+}
+
+fn svg_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
@@ -1,4 +1,5 @@
 pub use crate::*;
 pub mod app;
 pub mod error;
+pub mod export;
 pub mod ui;
@@ -1,8 +1,11 @@
 use crate::{
     client::app::{App, AppCanvas},
     client::error::Result,
-    data::{Coord, Message},
-    server::skribbl::{PlayerState, SkribblState},
+    data::{Coord, Message, PlayerListEntry},
+    server::{
+        lobby::LobbyState,
+        skribbl::{PlayerState, SkribblState},
+    },
 };
 
 use super::Username;
@@ -14,6 +17,9 @@ use tui::{
     Terminal,
 };
 
+/// how many columns the chat and the game state need next to the canvas
+pub const SIDEBAR_WIDTH: u16 = 30;
+
 pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()> {
     let dimensions = app.canvas.dimensions;
     terminal.draw(|mut f| {
@@ -35,18 +41,29 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
             )
             .split(size);
 
+        let (canvas, brush_title) = match &app.replay {
+            Some(replay) => (&replay.canvas, "Replay".to_string()),
+            None => (
+                &app.canvas,
+                match app.current_shape {
+                    Some(shape) => format!("Brush size: {}, {:?}", app.current_thickness, shape),
+                    None => format!("Brush size: {}", app.current_thickness),
+                },
+            ),
+        };
         let canvas_widget = CanvasWidget::new(
-            &app.canvas,
+            canvas,
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(app.current_color.into())),
+                .border_style(Style::default().fg(app.current_color.into()))
+                .title(&brush_title),
         );
 
-        let game_state_height = app
-            .game_state
-            .as_ref()
-            .map(|x| x.player_states.len() + 3)
-            .unwrap_or(0) as u16;
+        let game_state_height = match (&app.game_state, &app.lobby_state) {
+            (Some(state), _) => state.player_states.len() + 3,
+            (None, Some(lobby)) => lobby.ready_users.len() + 3,
+            (None, None) => app.players.iter().filter(|p| !p.spectator).count() + 2,
+        } as u16;
 
         let sidebar_chunks = Layout::default()
             .direction(Direction::Vertical)
@@ -54,14 +71,27 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
             .constraints([Length(game_state_height), Percentage(100)].as_ref())
             .split(main_chunks[1]);
 
+        let remaining_secs = app.remaining_secs();
         if let Some(skribbl_state) = app.game_state.as_mut() {
             let skribbl_widget = SkribblStateWidget::new(
                 &skribbl_state,
+                &app.scoreboard,
                 &app.session.username,
-                app.remaining_time.unwrap_or(0),
+                remaining_secs,
+                app.guess_progress,
                 Block::default().borders(Borders::NONE),
             );
             f.render_widget(skribbl_widget, sidebar_chunks[0]);
+        } else if let Some(lobby_state) = app.lobby_state.as_ref() {
+            let lobby_widget =
+                LobbyWidget::new(lobby_state, Block::default().borders(Borders::NONE));
+            f.render_widget(lobby_widget, sidebar_chunks[0]);
+        } else {
+            let player_list_widget = PlayerListWidget::new(
+                &app.players,
+                Block::default().borders(Borders::ALL).title("Players"),
+            );
+            f.render_widget(player_list_widget, sidebar_chunks[0]);
         }
 
         let canvas_rect = Rect {
@@ -73,7 +103,7 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
         let displayed_messages = (&app.chat.messages)
             .iter()
             .filter(|msg| match msg {
-                Message::SystemMsg(_) => true,
+                Message::SystemMsg(_) | Message::Whisper { .. } => true,
                 Message::UserMsg(username, _) => app.game_state.as_ref().map_or(true, |state| {
                     app.is_drawing()
                         || app.own_player().map_or(false, |x| x.has_solved)
@@ -89,6 +119,9 @@ pub fn draw<B: Backend>(app: &mut App, terminal: &mut Terminal<B>) -> Result<()>
         let chat_widget = ChatWidget::new(
             displayed_messages.as_slice(),
             &app.chat.input,
+            app.spectator_count,
+            &app.typing_users,
+            &app.players,
             Block::default().borders(Borders::NONE),
         );
         f.render_widget(chat_widget, sidebar_chunks[1]);
@@ -112,13 +145,15 @@ impl<'a, 't, 'b> Widget for CanvasWidget<'a, 't> {
         self.block.render(area, buf);
         let area = self.block.inner(area);
 
-        for line in self.canvas.lines.iter() {
-            for cell in line.coords_in() {
-                if cell.within(
+        for (y, row) in self.canvas.rasterize().into_iter().enumerate() {
+            for (x, color) in row.into_iter().enumerate() {
+                let cell = Coord(x as u16, y as u16);
+                let is_visible = cell.within(
                     &Coord(area.x, area.y),
                     &Coord(area.x + area.width, area.y + area.height),
-                ) {
-                    buf.get_mut(cell.0, cell.1).set_bg(line.color.into());
+                );
+                if let (Some(color), true) = (color, is_visible) {
+                    buf.get_mut(cell.0, cell.1).set_bg(color.into());
                 }
             }
         }
@@ -136,14 +171,27 @@ pub struct ChatWidget<'a, 't> {
     block: Block<'a>,
     messages: &'t [&'t Message],
     input: &'t str,
+    spectator_count: usize,
+    typing_users: &'t [Username],
+    players: &'t [PlayerListEntry],
 }
 
 impl<'a, 't> ChatWidget<'a, 't> {
-    pub fn new(messages: &'t [&Message], input: &'t str, block: Block<'a>) -> ChatWidget<'a, 't> {
+    pub fn new(
+        messages: &'t [&Message],
+        input: &'t str,
+        spectator_count: usize,
+        typing_users: &'t [Username],
+        players: &'t [PlayerListEntry],
+        block: Block<'a>,
+    ) -> ChatWidget<'a, 't> {
         ChatWidget {
             block,
             messages,
             input,
+            spectator_count,
+            typing_users,
+            players,
         }
     }
 }
@@ -159,21 +207,40 @@ impl<'a, 't, 'b> Widget for ChatWidget<'a, 't> {
             .constraints([Length(3), Percentage(100)].as_ref())
             .split(area);
 
+        let input_title = match self.typing_users {
+            [] => "Your message".to_string(),
+            [username] => format!("Your message ({} is typing)", username),
+            users => format!("Your message ({} people are typing)", users.len()),
+        };
         Paragraph::new([Text::Raw(self.input.clone().into())].iter())
-            .block(Block::default().borders(Borders::ALL).title("Your message"))
+            .block(Block::default().borders(Borders::ALL).title(&input_title))
             .render(chunks[0], buf);
 
         List::new(self.messages.iter().rev().map(|msg| {
             Text::styled(
                 format!("{}", msg),
-                if msg.is_system() {
-                    Style::default().fg(Color::Cyan)
-                } else {
-                    Style::default()
+                match msg {
+                    Message::SystemMsg(_) => Style::default().fg(Color::Cyan),
+                    Message::Whisper { .. } => Style::default().fg(Color::Magenta),
+                    Message::UserMsg(username, _) => self
+                        .players
+                        .iter()
+                        .find(|player| player.username == *username)
+                        .map_or(Style::default(), |player| {
+                            Style::default().fg(player.color.into())
+                        }),
                 },
             )
         }))
-        .block(Block::default().borders(Borders::ALL).title("Chat"))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(&match self.spectator_count {
+                    0 => "Chat".to_string(),
+                    1 => "Chat (1 spectator)".to_string(),
+                    cnt => format!("Chat ({} spectators)", cnt),
+                }),
+        )
         .render(chunks[1], buf);
     }
 }
@@ -181,21 +248,27 @@ impl<'a, 't, 'b> Widget for ChatWidget<'a, 't> {
 pub struct SkribblStateWidget<'a, 't> {
     block: Block<'a>,
     state: &'t SkribblState,
+    scoreboard: &'t [(Username, u32)],
     username: &'t Username,
     remaining_time: u32,
+    guess_progress: Option<(usize, usize)>,
 }
 impl<'a, 't> SkribblStateWidget<'a, 't> {
     pub fn new(
         state: &'t SkribblState,
+        scoreboard: &'t [(Username, u32)],
         username: &'t Username,
         remaining_time: u32,
+        guess_progress: Option<(usize, usize)>,
         block: Block<'a>,
     ) -> SkribblStateWidget<'a, 't> {
         SkribblStateWidget {
             block,
             state,
+            scoreboard,
             username,
             remaining_time,
+            guess_progress,
         }
     }
 }
@@ -213,19 +286,31 @@ impl<'a, 't, 'b> Widget for SkribblStateWidget<'a, 't> {
 
         let is_drawing = self.state.drawing_user == *self.username;
 
-        let current_word_representation = if is_drawing {
-            self.state.current_word().to_string()
+        let header = if self.state.is_paused() {
+            "Waiting for more players".to_string()
+        } else if self.state.is_between_turns() {
+            format!("{} draws next", self.state.drawing_user)
+        } else if self.state.is_choosing_word() {
+            format!("{} is choosing a word", self.state.drawing_user)
+        } else if let Some((solved, total)) = self.guess_progress {
+            format!(
+                "{} drawing {} ({}/{} guessed)",
+                self.state.drawing_user,
+                self.state.current_word(),
+                solved,
+                total
+            )
         } else {
-            self.state.hinted_current_word().to_string()
+            format!(
+                "{} drawing {}",
+                self.state.drawing_user,
+                self.state.current_word()
+            )
         };
 
         Paragraph::new(
             [Text::Styled(
-                format!(
-                    "{} drawing {}",
-                    self.state.drawing_user, current_word_representation
-                )
-                .into(),
+                header.into(),
                 if is_drawing {
                     Style::default().bg(Color::Red)
                 } else {
@@ -241,14 +326,29 @@ impl<'a, 't, 'b> Widget for SkribblStateWidget<'a, 't> {
             .player_states
             .iter()
             .collect::<Vec<(&Username, &PlayerState)>>();
-        sorted_player_entries.sort_by_key(|(x, _)| *x);
+        // best player first, as the server ranks them. Players the scoreboard doesn't know yet go last
+        sorted_player_entries.sort_by_key(|(username, _)| {
+            let rank = self
+                .scoreboard
+                .iter()
+                .position(|(ranked, _)| ranked == *username);
+            (rank.unwrap_or(usize::MAX), *username)
+        });
 
         List::new(
             sorted_player_entries
                 .into_iter()
                 .map(|(username, player_state)| {
+                    let mut score = if player_state.last_points > 0 {
+                        format!("{} (+{})", player_state.score, player_state.last_points)
+                    } else {
+                        player_state.score.to_string()
+                    };
+                    if player_state.streak > 1 {
+                        score.push_str(&format!(" [{} in a row]", player_state.streak));
+                    }
                     Text::styled(
-                        format!("{}: {}", username, player_state.score,),
+                        format!("{}: {}", username, score),
                         if self.state.drawing_user == *username {
                             Style::default().bg(tui::style::Color::Cyan)
                         } else if self.state.has_solved(username) {
@@ -259,11 +359,86 @@ impl<'a, 't, 'b> Widget for SkribblStateWidget<'a, 't> {
                     )
                 }),
         )
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(&format!("Players [time: {}]", self.remaining_time)),
-        )
+        .block(Block::default().borders(Borders::ALL).title(&format!(
+            "Players [round: {}/{}, time: {}]",
+            self.state.round, self.state.settings.total_rounds, self.remaining_time
+        )))
         .render(chunks[1], buf);
     }
 }
+
+pub struct LobbyWidget<'a, 't> {
+    block: Block<'a>,
+    state: &'t LobbyState,
+}
+
+impl<'a, 't> LobbyWidget<'a, 't> {
+    pub fn new(state: &'t LobbyState, block: Block<'a>) -> LobbyWidget<'a, 't> {
+        LobbyWidget { block, state }
+    }
+}
+
+impl<'a, 't> Widget for LobbyWidget<'a, 't> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        self.block.render(area, buf);
+        let area = self.block.inner(area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints([Constraint::Length(1), Constraint::Percentage(100)].as_ref())
+            .split(area);
+
+        Paragraph::new([Text::Raw("Type !ready when you're ready to play".into())].iter())
+            .render(chunks[0], buf);
+
+        List::new(self.state.ready_users.iter().map(|username| {
+            Text::styled(
+                username.to_string(),
+                Style::default().fg(tui::style::Color::Green),
+            )
+        }))
+        .block(Block::default().borders(Borders::ALL).title(&format!(
+            "Ready [{}/{}]",
+            self.state.ready_users.len(),
+            self.state.min_ready_players
+        )))
+        .render(chunks[1], buf);
+    }
+}
+
+pub struct PlayerListWidget<'a, 't> {
+    block: Block<'a>,
+    players: &'t [PlayerListEntry],
+}
+
+impl<'a, 't> PlayerListWidget<'a, 't> {
+    pub fn new(players: &'t [PlayerListEntry], block: Block<'a>) -> PlayerListWidget<'a, 't> {
+        PlayerListWidget { block, players }
+    }
+}
+
+impl<'a, 't> Widget for PlayerListWidget<'a, 't> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        List::new(
+            self.players
+                .iter()
+                .filter(|player| !player.spectator)
+                .map(|player| {
+                    if player.connected {
+                        Text::styled(
+                            player.username.to_string(),
+                            Style::default().fg(player.color.into()),
+                        )
+                    } else {
+                        Text::styled(
+                            format!("{} (away)", player.username),
+                            Style::default().fg(Color::DarkGray),
+                        )
+                    }
+                }),
+        )
+        .block(self.block)
+        .render(area, buf);
+    }
+}
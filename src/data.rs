@@ -1,10 +1,36 @@
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, fmt::Display};
+use std::{cmp::Ordering, fmt::Display, str::FromStr};
 use tui::style::Color;
 
+/// the longest username, in characters, the server accepts
+pub const MAX_USERNAME_LENGTH: usize = 20;
+
 #[derive(Eq, PartialEq, Hash, Debug, Clone, Serialize, Deserialize, Ord, PartialOrd)]
 pub struct Username(String);
 
+impl Username {
+    /// turns a name a client sent into a username, stripping control characters
+    /// and surrounding whitespace. Returns the reason if the name can't be used.
+    pub fn sanitized(name: &str) -> Result<Username, String> {
+        let name = name
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect::<String>()
+            .trim()
+            .to_string();
+        if name.is_empty() {
+            Err("The name must not be empty".to_string())
+        } else if name.chars().count() > MAX_USERNAME_LENGTH {
+            Err(format!(
+                "The name must not be longer than {} characters",
+                MAX_USERNAME_LENGTH
+            ))
+        } else {
+            Ok(Username(name))
+        }
+    }
+}
+
 impl From<String> for Username {
     fn from(s: String) -> Self {
         Username(s)
@@ -23,6 +49,12 @@ impl Display for Username {
     }
 }
 
+/// the thickest line, in cells, the server accepts
+pub const MAX_LINE_THICKNESS: u8 = 5;
+
+/// identifies one of the independent games running on a server
+pub type RoomId = String;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, Serialize, Deserialize)]
 pub struct Coord(pub u16, pub u16);
 
@@ -42,6 +74,13 @@ impl Coord {
     pub fn within(&self, a: &Coord, b: &Coord) -> bool {
         self > a.min(b) && self < a.max(b)
     }
+
+    /// moves the coordinate onto the closest cell of a canvas with the given dimensions
+    pub fn clamp_to(&self, dimensions: (usize, usize)) -> Coord {
+        let max_x = dimensions.0.saturating_sub(1).min(u16::MAX as usize) as u16;
+        let max_y = dimensions.1.saturating_sub(1).min(u16::MAX as usize) as u16;
+        Coord(self.0.min(max_x), self.1.min(max_y))
+    }
 }
 
 impl From<(i16, i16)> for Coord {
@@ -55,28 +94,244 @@ impl From<Coord> for (i16, i16) {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Line {
     pub start: Coord,
     pub end: Coord,
+    /// lines from before colors existed are white
+    #[serde(default)]
     pub color: CanvasColor,
+    /// width of the brush in cells. Lines from before thickness existed are one cell wide
+    #[serde(default = "default_thickness")]
+    pub thickness: u8,
+}
+
+fn default_thickness() -> u8 {
+    1
 }
 
 impl Line {
-    pub fn new(start: Coord, end: Coord, color: CanvasColor) -> Self {
-        Line { start, end, color }
+    pub fn new(start: Coord, end: Coord, color: CanvasColor, thickness: u8) -> Self {
+        Line {
+            start,
+            end,
+            color,
+            thickness,
+        }
+    }
+
+    /// limits the thickness to what clients can be expected to render
+    pub fn clamp_thickness(&mut self) {
+        self.thickness = self.thickness.clamp(1, MAX_LINE_THICKNESS);
     }
+
+    /// moves both ends of the line onto a canvas with the given dimensions
+    pub fn clamp_to(&mut self, dimensions: (usize, usize)) {
+        self.start = self.start.clamp_to(dimensions);
+        self.end = self.end.clamp_to(dimensions);
+    }
+
+    /// every cell the line covers. A thick line covers a square of
+    /// thickness x thickness cells around each point of the thin line.
     pub fn coords_in(&self) -> Vec<Coord> {
-        line_drawing::Bresenham::new(self.start.into(), self.end.into())
+        let thickness = self.thickness.clamp(1, MAX_LINE_THICKNESS) as u16;
+        let half = thickness / 2;
+        let mut coords = line_drawing::Bresenham::new(self.start.into(), self.end.into())
             .map(Coord::from)
-            .collect()
+            .flat_map(|Coord(x, y)| {
+                (0..thickness).flat_map(move |dx| {
+                    (0..thickness).map(move |dy| {
+                        Coord(x.saturating_sub(half) + dx, y.saturating_sub(half) + dy)
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        coords.sort_by_key(|Coord(x, y)| (*x, *y));
+        coords.dedup();
+        coords
+    }
+}
+
+/// a bucket fill, coloring the area around (x, y) that has the same color as (x, y)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fill {
+    pub x: u16,
+    pub y: u16,
+    pub color: CanvasColor,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShapeKind {
+    /// a straight line from one corner to the other
+    Line,
+    /// the outline of the bounding box
+    Rectangle,
+    /// the outline of the ellipse in the bounding box
+    Ellipse,
+}
+
+impl FromStr for ShapeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "line" => Ok(ShapeKind::Line),
+            "rectangle" | "rect" => Ok(ShapeKind::Rectangle),
+            "ellipse" | "circle" => Ok(ShapeKind::Ellipse),
+            _ => Err(format!("unknown shape {}", s)),
+        }
+    }
+}
+
+/// a shape drawn in one go, within the box that has start and end as opposite corners
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shape {
+    pub kind: ShapeKind,
+    pub start: Coord,
+    pub end: Coord,
+    pub color: CanvasColor,
+    pub thickness: u8,
+}
+
+impl Shape {
+    /// limits the thickness to what clients can be expected to render
+    pub fn clamp_thickness(&mut self) {
+        self.thickness = self.thickness.clamp(1, MAX_LINE_THICKNESS);
+    }
+
+    /// moves both corners of the shape onto a canvas with the given dimensions
+    pub fn clamp_to(&mut self, dimensions: (usize, usize)) {
+        self.start = self.start.clamp_to(dimensions);
+        self.end = self.end.clamp_to(dimensions);
+    }
+
+    /// the lines the outline of the shape is made of
+    pub fn lines(&self) -> Vec<Line> {
+        let (Coord(x0, y0), Coord(x1, y1)) = (self.start, self.end);
+        let line = |start, end| Line::new(start, end, self.color, self.thickness);
+        match self.kind {
+            ShapeKind::Line => vec![line(self.start, self.end)],
+            ShapeKind::Rectangle => vec![
+                line(Coord(x0, y0), Coord(x1, y0)),
+                line(Coord(x1, y0), Coord(x1, y1)),
+                line(Coord(x1, y1), Coord(x0, y1)),
+                line(Coord(x0, y1), Coord(x0, y0)),
+            ],
+            ShapeKind::Ellipse => {
+                let (center_x, center_y) =
+                    ((x0 as f64 + x1 as f64) / 2.0, (y0 as f64 + y1 as f64) / 2.0);
+                let (radius_x, radius_y) = (
+                    (x1 as f64 - x0 as f64).abs() / 2.0,
+                    (y1 as f64 - y0 as f64).abs() / 2.0,
+                );
+                // about one point per cell of the outline, so the lines between them stay short
+                let point_cnt = ((radius_x + radius_y) * 4.0).ceil().max(4.0) as usize;
+                let points = (0..=point_cnt)
+                    .map(|idx| {
+                        let angle = idx as f64 / point_cnt as f64 * std::f64::consts::TAU;
+                        Coord(
+                            (center_x + radius_x * angle.cos()).round() as u16,
+                            (center_y + radius_y * angle.sin()).round() as u16,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                points
+                    .windows(2)
+                    .map(|ends| line(ends[0], ends[1]))
+                    .collect()
+            }
+        }
     }
+
+    /// every cell the outline of the shape covers
+    pub fn coords_in(&self) -> Vec<Coord> {
+        let mut coords = self
+            .lines()
+            .iter()
+            .flat_map(Line::coords_in)
+            .collect::<Vec<_>>();
+        coords.sort_by_key(|Coord(x, y)| (*x, *y));
+        coords.dedup();
+        coords
+    }
+}
+
+/// one step of drawing on the canvas. Replaying them in order gives the picture
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CanvasOp {
+    Line(Line),
+    Fill(Fill),
+    Shape(Shape),
+}
+
+impl CanvasOp {
+    /// the op on a canvas with the given dimensions. Lines are moved onto it, fills outside of it are dropped
+    pub fn clamp_to(self, dimensions: (usize, usize)) -> Option<CanvasOp> {
+        match self {
+            CanvasOp::Line(mut line) => {
+                line.clamp_to(dimensions);
+                Some(CanvasOp::Line(line))
+            }
+            CanvasOp::Fill(fill)
+                if (fill.x as usize) < dimensions.0 && (fill.y as usize) < dimensions.1 =>
+            {
+                Some(CanvasOp::Fill(fill))
+            }
+            CanvasOp::Fill(_) => None,
+            CanvasOp::Shape(mut shape) => {
+                shape.clamp_to(dimensions);
+                Some(CanvasOp::Shape(shape))
+            }
+        }
+    }
+}
+
+/// a change to the canvas, as it is recorded for replays
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum CanvasEdit {
+    Op(CanvasOp),
+    /// the last line or fill was taken back
+    Undo,
+}
+
+/// one edit of a recorded drawing
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    /// milliseconds since the recording started
+    pub at: u32,
+    pub edit: CanvasEdit,
+}
+
+/// a chat message as the server sent it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedMessage {
+    pub message: Message,
+    /// when the server got the message, in milliseconds since the unix epoch.
+    /// This is the clock the server_time of TimeChanged is on
+    pub sent_at: u64,
+}
+
+/// someone in a room, as shown in the player list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerListEntry {
+    pub username: Username,
+    pub spectator: bool,
+    /// false while a player who lost the connection may still reconnect
+    pub connected: bool,
+    /// the color their chat messages are shown in
+    pub color: CanvasColor,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     SystemMsg(String),
     UserMsg(Username, String),
+    /// a private message, which only the sender and the receiver get to see
+    Whisper {
+        from: Username,
+        to: Username,
+        text: String,
+    },
 }
 
 impl Message {
@@ -84,6 +339,7 @@ impl Message {
         match self {
             Message::SystemMsg(msg) => &msg,
             Message::UserMsg(_, msg) => &msg,
+            Message::Whisper { text, .. } => text,
         }
     }
 
@@ -97,6 +353,7 @@ impl Message {
     pub fn username(&self) -> Option<&Username> {
         match self {
             Message::UserMsg(username, _) => Some(username),
+            Message::Whisper { from, .. } => Some(from),
             _ => None,
         }
     }
@@ -107,12 +364,50 @@ impl Display for Message {
         match self {
             Message::SystemMsg(msg) => write!(f, "{}", msg),
             Message::UserMsg(user, msg) => write!(f, "{}: {}", user, msg),
+            Message::Whisper { from, to, text } => write!(f, "{} -> {}: {}", from, to, text),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+/// the colors players can draw with, in the order they are shown
+pub const PALETTE: [CanvasColor; 16] = [
+    CanvasColor::White,
+    CanvasColor::Gray,
+    CanvasColor::DarkGray,
+    CanvasColor::Black,
+    CanvasColor::Red,
+    CanvasColor::LightRed,
+    CanvasColor::Green,
+    CanvasColor::LightGreen,
+    CanvasColor::Blue,
+    CanvasColor::LightBlue,
+    CanvasColor::Yellow,
+    CanvasColor::LightYellow,
+    CanvasColor::Cyan,
+    CanvasColor::LightCyan,
+    CanvasColor::Magenta,
+    CanvasColor::LightMagenta,
+];
+
+/// the colors users are told apart by in the chat. All of them show on a black background
+pub const USER_COLORS: [CanvasColor; 12] = [
+    CanvasColor::Red,
+    CanvasColor::Green,
+    CanvasColor::Blue,
+    CanvasColor::Yellow,
+    CanvasColor::Magenta,
+    CanvasColor::Cyan,
+    CanvasColor::LightRed,
+    CanvasColor::LightGreen,
+    CanvasColor::LightBlue,
+    CanvasColor::LightYellow,
+    CanvasColor::LightMagenta,
+    CanvasColor::LightCyan,
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
 pub enum CanvasColor {
+    #[default]
     White,
     Gray,
     DarkGray,
@@ -131,6 +426,30 @@ pub enum CanvasColor {
     LightMagenta,
 }
 
+impl CanvasColor {
+    /// the color as red, green and blue, as the default xterm palette shows it
+    pub fn rgb(self) -> [u8; 3] {
+        match self {
+            CanvasColor::White => [255, 255, 255],
+            CanvasColor::Gray => [192, 192, 192],
+            CanvasColor::DarkGray => [128, 128, 128],
+            CanvasColor::Black => [0, 0, 0],
+            CanvasColor::Red => [205, 0, 0],
+            CanvasColor::LightRed => [255, 0, 0],
+            CanvasColor::Green => [0, 205, 0],
+            CanvasColor::LightGreen => [0, 255, 0],
+            CanvasColor::Blue => [0, 0, 238],
+            CanvasColor::LightBlue => [92, 92, 255],
+            CanvasColor::Yellow => [205, 205, 0],
+            CanvasColor::LightYellow => [255, 255, 0],
+            CanvasColor::Cyan => [0, 205, 205],
+            CanvasColor::LightCyan => [0, 255, 255],
+            CanvasColor::Magenta => [205, 0, 205],
+            CanvasColor::LightMagenta => [255, 0, 255],
+        }
+    }
+}
+
 impl From<CanvasColor> for Color {
     fn from(c: CanvasColor) -> Self {
         match c {
@@ -156,5 +475,89 @@ impl From<CanvasColor> for Color {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CommandMsg {
+    /// remove a player right away. Only the host may do this
     KickPlayer(Username),
+    /// drop all chat messages of a player until they are unmuted. Only the host may do this
+    MutePlayer(Username),
+    UnmutePlayer(Username),
+    /// remove a player and refuse any further connections from their address.
+    /// Only the host may do this
+    BanPlayer(Username),
+    /// let the addresses of a banned player connect again. Only the host may do this
+    UnbanPlayer(Username),
+    /// vote to remove a player. They are removed once enough of the other players voted for it
+    VoteKick(Username),
+    /// start the game from the lobby, even if not enough players are ready. Only the host may do this
+    StartGame,
+    /// vote to end the current turn early, e.g. because the drawing user isn't drawing anything
+    VoteSkip,
+    /// pause the running game, or continue it if the host paused it. Only the host may do this
+    TogglePause,
+    /// watch the previous drawing being drawn again, at its original speed
+    ReplayDrawing,
+    /// replace the word of the current turn. Only the host may do this
+    SetWord(String),
+    /// set every player's score back to zero. Only the host may do this
+    ResetScores,
+    /// start a new game with everyone in the room, whatever is going on. Only the host may do this
+    RestartGame,
+    /// end the game or leave the lobby, so everyone may draw. Only the host may do this
+    StopGame,
+    /// read the word lists of the server again, for the next game in the room. Only the host may do this
+    ReloadWords,
+    /// add a word to the room's word list, optionally with a difficulty like `hard:word`.
+    /// Only the host may do this
+    AddWord(String),
+    /// take a word out of the room's word list. Only the host may do this
+    RemoveWord(String),
+}
+
+impl CommandMsg {
+    /// whether only the host of the room may send this command
+    pub fn is_privileged(&self) -> bool {
+        match self {
+            CommandMsg::KickPlayer(_)
+            | CommandMsg::BanPlayer(_)
+            | CommandMsg::UnbanPlayer(_)
+            | CommandMsg::MutePlayer(_)
+            | CommandMsg::UnmutePlayer(_)
+            | CommandMsg::StartGame
+            | CommandMsg::TogglePause
+            | CommandMsg::SetWord(_)
+            | CommandMsg::ResetScores
+            | CommandMsg::RestartGame
+            | CommandMsg::StopGame
+            | CommandMsg::ReloadWords
+            | CommandMsg::AddWord(_)
+            | CommandMsg::RemoveWord(_) => true,
+            CommandMsg::VoteKick(_) | CommandMsg::VoteSkip | CommandMsg::ReplayDrawing => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_outside_the_canvas_are_clamped() {
+        let mut line = Line::new(Coord(500, 3), Coord(2, 9000), CanvasColor::White, 1);
+        line.clamp_to((100, 50));
+        assert_eq!(line.start, Coord(99, 3));
+        assert_eq!(line.end, Coord(2, 49));
+    }
+
+    #[test]
+    fn fills_outside_the_canvas_are_dropped() {
+        let fill = |x, y| {
+            CanvasOp::Fill(Fill {
+                x,
+                y,
+                color: CanvasColor::White,
+            })
+        };
+        assert!(fill(500, 3).clamp_to((100, 50)).is_none());
+        assert!(fill(2, 9000).clamp_to((100, 50)).is_none());
+        assert!(fill(99, 49).clamp_to((100, 50)).is_some());
+    }
 }
@@ -1,10 +1,13 @@
 pub mod client;
 pub mod data;
 pub mod message;
+pub mod render;
 pub mod server;
 
 use std::io::{stdout, Write};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 use crossterm::{
@@ -18,7 +21,13 @@ use tui::{backend::CrosstermBackend, Terminal};
 
 use client::app::ServerSession;
 use data::Username;
+use message::{Codec, JoinRequest, PROTOCOL_VERSION};
 pub use serde::{Deserialize, Serialize};
+use server::{
+    config::ServerConfig,
+    server::TlsFiles,
+    skribbl::{Difficulty, GameSettings},
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Termibbl", about = "A Skribbl.io-alike for the terminal")]
@@ -28,18 +37,220 @@ struct Opt {
 }
 
 #[derive(Debug, StructOpt)]
+#[allow(clippy::large_enum_variant)]
 enum SubOpt {
     Server {
-        #[structopt(long = "--port", short = "-p")]
-        port: u32,
-        #[structopt(long = "--words", parse(from_os_str), required_if("freedraw", "true"))]
-        word_file: Option<PathBuf>,
+        #[structopt(
+            long = "--port",
+            short = "-p",
+            help = "port to listen on. Without it, TERMIBBL_ADDR or port 8888 is used"
+        )]
+        port: Option<u16>,
+        #[structopt(
+            long,
+            help = "IP address to listen on. Use :: for IPv6, which usually accepts IPv4 connections too [default: 0.0.0.0]"
+        )]
+        bind: Option<IpAddr>,
+        #[structopt(
+            long = "--words",
+            parse(from_os_str),
+            required_if("freedraw", "true"),
+            help = "files or http(s) URLs with the words to draw. Several lists are merged into one"
+        )]
+        word_files: Vec<PathBuf>,
         #[structopt(short, long, help = "<width>x<height>", parse(from_str = crate::parse_dimension), default_value = "100x50")]
         dimensions: (usize, usize),
+        #[structopt(long, help = "length of a turn in seconds [default: 120]")]
+        round_duration: Option<u64>,
+        #[structopt(
+            long,
+            help = "seconds between two turns, to read what the word was [default: 3]"
+        )]
+        turn_break: Option<u64>,
+        #[structopt(
+            long,
+            parse(try_from_str = crate::parse_hint_thresholds),
+            help = "fractions of a turn after which another letter of the word is revealed, like 0.4,0.7 [default: 0.4,0.7]"
+        )]
+        hint_thresholds: Option<HintThresholds>,
+        #[structopt(
+            long,
+            help = "seconds a player may be idle before being removed from the game [default: 180]"
+        )]
+        idle_timeout: Option<u64>,
+        #[structopt(
+            long,
+            help = "how many players have to be ready for a game to start [default: 2]"
+        )]
+        min_ready: Option<usize>,
+        #[structopt(
+            long,
+            help = "with fewer players, a running game pauses until more join [default: 2]"
+        )]
+        min_players: Option<usize>,
+        #[structopt(long, help = "how often every player draws in a game [default: 3]")]
+        rounds: Option<u32>,
+        #[structopt(
+            long,
+            help = "easy, normal or hard. Words of this difficulty are picked more often"
+        )]
+        difficulty: Option<Difficulty>,
+        #[structopt(
+            long,
+            help = "fraction of the other players that has to vote to kick a player [default: 0.5]"
+        )]
+        vote_kick_fraction: Option<f64>,
+        #[structopt(
+            long,
+            help = "points for guessing the word right at the start of a turn [default: 100]"
+        )]
+        guess_points: Option<u32>,
+        #[structopt(
+            long,
+            help = "fraction of the guess points that is lost by the end of the turn [default: 0.9]"
+        )]
+        guess_points_decay: Option<f64>,
+        #[structopt(
+            long,
+            help = "fraction of the guess points the drawing player gets for every correct guess [default: 0.5]"
+        )]
+        drawer_share: Option<f64>,
+        #[structopt(
+            long,
+            help = "the most points the drawing player can get in a turn [default: 150]"
+        )]
+        max_drawer_points: Option<u32>,
+        #[structopt(
+            long,
+            help = "extra points in percent for every word guessed in a row before [default: 10]"
+        )]
+        streak_bonus: Option<u32>,
+        #[structopt(
+            long,
+            help = "the most extra points in percent a streak gives [default: 50]"
+        )]
+        max_streak_bonus: Option<u32>,
+        #[structopt(long, help = "how many players a room accepts, spectators not counted")]
+        max_players: Option<usize>,
+        #[structopt(long, help = "only let clients with this password join")]
+        password: Option<String>,
+        #[structopt(
+            long,
+            help = "clients joining with this key become the host of their room"
+        )]
+        host_key: Option<String>,
+        #[structopt(
+            long,
+            help = "seconds a client may not answer pings before it is dropped [default: 30]"
+        )]
+        pong_timeout: Option<u64>,
+        #[structopt(
+            long,
+            help = "seconds a player whose connection dropped may reconnect without losing their score [default: 30]"
+        )]
+        reconnect_grace_period: Option<u64>,
+        #[structopt(
+            long,
+            help = "how many messages may wait to be sent to a client [default: 64]"
+        )]
+        session_buffer: Option<usize>,
+        #[structopt(
+            long,
+            help = "seconds a client may not take any messages before it is dropped [default: 5]"
+        )]
+        slow_client_timeout: Option<u64>,
+        #[structopt(
+            long,
+            help = "milliseconds between two updates of the game clock. Shorter makes the countdown smoother, longer saves CPU time [default: 500]"
+        )]
+        tick_interval: Option<u64>,
+        #[structopt(
+            long,
+            help = "how many lines and fills the canvas holds before it has to be cleared [default: 5000]"
+        )]
+        max_lines: Option<usize>,
+        #[structopt(
+            long,
+            help = "how many lines a client may draw per second before further lines are dropped [default: 200]"
+        )]
+        max_line_rate: Option<u32>,
+        #[structopt(
+            long,
+            help = "how many characters a chat message may have [default: 500]"
+        )]
+        max_message_length: Option<usize>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "file with words, one per line, that are replaced with asterisks in the chat"
+        )]
+        blocklist: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "JSON file to keep the players' stats in across restarts"
+        )]
+        stats_file: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "JSON file to keep the banned addresses in across restarts"
+        )]
+        ban_file: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "file the rooms are saved to on shutdown, and restored from on startup"
+        )]
+        resume: Option<PathBuf>,
+        #[structopt(long, help = "serve Prometheus metrics at /metrics on this port")]
+        metrics_port: Option<u16>,
+        #[structopt(
+            long,
+            help = "answer health checks at /health on this port, may be the same as --metrics-port"
+        )]
+        health_port: Option<u16>,
+        #[structopt(
+            long,
+            help = "serve the canvas of a room as a PNG image at /canvas.png?room=<room> on this port, over plain HTTP"
+        )]
+        canvas_port: Option<u16>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires("key-file"),
+            help = "PEM certificate chain to serve wss with"
+        )]
+        cert_file: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires("cert-file"),
+            help = "PEM private key for --cert-file"
+        )]
+        key_file: Option<PathBuf>,
     },
     Client {
         #[structopt(long = "address", short = "-a")]
         addr: String,
+        #[structopt(long, short, help = "the game room to join", default_value = "default")]
+        room: String,
+        #[structopt(long, help = "the password of the server, if it has one")]
+        password: Option<String>,
+        #[structopt(long, help = "use the compact binary encoding instead of JSON")]
+        binary: bool,
+        #[structopt(long, help = "watch the game without drawing, chatting or guessing")]
+        spectate: bool,
+        #[structopt(
+            long,
+            help = "the host key of the server, to become the host of the room"
+        )]
+        host_key: Option<String>,
+        #[structopt(
+            long,
+            help = "the token the server gave you, to get your place back after your connection dropped"
+        )]
+        reconnect_token: Option<String>,
         username: String,
     },
 }
@@ -52,37 +263,242 @@ fn parse_dimension(s: &str) -> (usize, usize) {
     )
 }
 
+/// named, as structopt would otherwise expect a value per threshold rather than the whole list
+type HintThresholds = Vec<f64>;
+
+/// comma separated fractions of a turn, each between 0 and 1, from the earliest to the latest
+fn parse_hint_thresholds(s: &str) -> std::result::Result<HintThresholds, String> {
+    let thresholds = s
+        .split(',')
+        .map(|threshold| match threshold.trim().parse::<f64>() {
+            Ok(threshold) if threshold > 0.0 && threshold < 1.0 => Ok(threshold),
+            _ => Err(format!("{} is not a number between 0 and 1", threshold)),
+        })
+        .collect::<std::result::Result<Vec<f64>, String>>()?;
+    if thresholds.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err("the thresholds have to be sorted, earliest first".to_string());
+    }
+    Ok(thresholds)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opt = Opt::from_args();
     match opt.cmd {
-        SubOpt::Client { username, addr } => {
+        SubOpt::Client {
+            username,
+            addr,
+            room,
+            password,
+            binary,
+            spectate,
+            host_key,
+            reconnect_token,
+        } => {
             let addr = if addr.starts_with("ws://") || addr.starts_with("wss://") {
                 addr
             } else {
                 format!("ws://{}", addr)
             };
-            run_client(&addr, username.into()).await.unwrap();
+            let username = match Username::sanitized(&username) {
+                Ok(username) => username,
+                Err(reason) => {
+                    eprintln!("Invalid username: {}", reason);
+                    std::process::exit(1);
+                }
+            };
+            let join_request = JoinRequest {
+                protocol_version: PROTOCOL_VERSION,
+                username: username.into(),
+                room,
+                password,
+                codec: if binary { Codec::Binary } else { Codec::Json },
+                spectator: spectate,
+                host_key,
+                reconnect_token,
+                // the canvas gets the space the sidebar leaves
+                dimensions: crossterm::terminal::size().ok().map(|(width, height)| {
+                    let width = width.saturating_sub(client::ui::SIDEBAR_WIDTH);
+                    (width as usize, height as usize)
+                }),
+            };
+            match run_client(&addr, join_request).await {
+                Err(client::error::Error::JoinRejected(reason)) => {
+                    eprintln!("Could not join the game: {}", reason);
+                    std::process::exit(1);
+                }
+                result => result.unwrap(),
+            }
         }
         SubOpt::Server {
             port,
-            word_file,
+            bind,
+            word_files,
             dimensions,
+            round_duration,
+            turn_break,
+            hint_thresholds,
+            idle_timeout,
+            min_ready,
+            min_players,
+            rounds,
+            difficulty,
+            vote_kick_fraction,
+            guess_points,
+            guess_points_decay,
+            drawer_share,
+            max_drawer_points,
+            streak_bonus,
+            max_streak_bonus,
+            max_players,
+            password,
+            host_key,
+            pong_timeout,
+            reconnect_grace_period,
+            session_buffer,
+            slow_client_timeout,
+            tick_interval,
+            max_lines,
+            max_line_rate,
+            max_message_length,
+            blocklist,
+            stats_file,
+            ban_file,
+            resume,
+            metrics_port,
+            health_port,
+            canvas_port,
+            cert_file,
+            key_file,
         } => {
-            tokio::spawn(async move {
-                if let Ok(res) = reqwest::get("http://ifconfig.me").await {
-                    if let Ok(ip) = res.text().await {
-                        println!("Starting server!");
-                        println!("Your public IP is {}:{}", ip, port);
-                        println!("You can find out your private IP by running \"ifconfig\" in the terminal");
-                    }
-                }
-            });
+            // RUST_LOG controls what is logged, e.g. RUST_LOG=warn or RUST_LOG=termibbl=debug
+            let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+            tracing_subscriber::fmt().with_env_filter(env_filter).init();
 
-            let addr = format!("0.0.0.0:{}", port);
-            server::server::run_server(&addr, dimensions, word_file)
-                .await
-                .unwrap();
+            let mut settings = GameSettings::default();
+            if let Some(turn_break) = turn_break {
+                settings.turn_break = Duration::from_secs(turn_break);
+            }
+            if let Some(hint_thresholds) = hint_thresholds {
+                settings.hint_thresholds = hint_thresholds;
+            }
+            if let Some(idle_timeout) = idle_timeout {
+                settings.idle_timeout = Duration::from_secs(idle_timeout);
+            }
+            if let Some(min_ready) = min_ready {
+                settings.min_ready_players = min_ready;
+            }
+            if let Some(min_players) = min_players {
+                settings.min_players = min_players;
+            }
+            if let Some(rounds) = rounds {
+                settings.total_rounds = rounds;
+            }
+            settings.difficulty = difficulty;
+            if let Some(vote_kick_fraction) = vote_kick_fraction {
+                settings.vote_kick_fraction = vote_kick_fraction;
+            }
+            if let Some(guess_points) = guess_points {
+                settings.scoring.guess_points = guess_points;
+            }
+            if let Some(guess_points_decay) = guess_points_decay {
+                settings.scoring.guess_points_decay = guess_points_decay;
+            }
+            if let Some(drawer_share) = drawer_share {
+                settings.scoring.drawer_share = drawer_share;
+            }
+            if let Some(max_drawer_points) = max_drawer_points {
+                settings.scoring.max_drawer_points = max_drawer_points;
+            }
+            if let Some(streak_bonus) = streak_bonus {
+                settings.scoring.streak_bonus_percent = streak_bonus;
+            }
+            if let Some(max_streak_bonus) = max_streak_bonus {
+                settings.scoring.max_streak_bonus_percent = max_streak_bonus;
+            }
+            let tls_files = cert_file
+                .zip(key_file)
+                .map(|(cert_file, key_file)| TlsFiles {
+                    cert_file,
+                    key_file,
+                });
+            let config = ServerConfig::builder().settings(settings).env();
+            let mut config = match config {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Could not run the server: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            // the arguments take precedence over the environment
+            config = config.bind(bind, port);
+            if let Some(round_duration) = round_duration {
+                config = config.round_duration(Duration::from_secs(round_duration));
+            }
+            if !word_files.is_empty() {
+                config = config.word_sources(word_files);
+            }
+            if max_players.is_some() {
+                config = config.max_players(max_players);
+            }
+            if password.is_some() {
+                config = config.password(password);
+            }
+            if host_key.is_some() {
+                config = config.host_key(host_key);
+            }
+            let mut config = config
+                .dimensions(dimensions)
+                .tls_files(tls_files)
+                .blocklist_file(blocklist)
+                .stats_file(stats_file)
+                .ban_file(ban_file)
+                .resume_file(resume)
+                .metrics_port(metrics_port)
+                .health_port(health_port)
+                .canvas_port(canvas_port);
+            if let Some(pong_timeout) = pong_timeout {
+                config = config.pong_timeout(Duration::from_secs(pong_timeout));
+            }
+            if let Some(reconnect_grace_period) = reconnect_grace_period {
+                config = config.reconnect_grace_period(Duration::from_secs(reconnect_grace_period));
+            }
+            if let Some(session_buffer) = session_buffer {
+                config = config.session_buffer(session_buffer);
+            }
+            if let Some(slow_client_timeout) = slow_client_timeout {
+                config = config.slow_client_timeout(Duration::from_secs(slow_client_timeout));
+            }
+            if let Some(tick_interval) = tick_interval {
+                config = config.tick_interval(Duration::from_millis(tick_interval));
+            }
+            if let Some(max_lines) = max_lines {
+                config = config.max_lines(max_lines);
+            }
+            if let Some(max_line_rate) = max_line_rate {
+                config = config.max_line_rate(max_line_rate);
+            }
+            if let Some(max_message_length) = max_message_length {
+                config = config.max_message_length(max_message_length);
+            }
+            let config = config.build();
+            if let Ok(addr) = config.addr.parse::<SocketAddr>() {
+                tokio::spawn(async move {
+                    if let Ok(res) = reqwest::get("http://ifconfig.me").await {
+                        if let Ok(ip) = res.text().await {
+                            tracing::info!("Starting server!");
+                            tracing::info!("Your public IP is {}:{}", ip, addr.port());
+                            tracing::info!("You can find out your private IP by running \"ifconfig\" in the terminal");
+                        }
+                    }
+                });
+            }
+            let result = server::server::run_server(config).await;
+            if let Err(err) = result {
+                eprintln!("Could not run the server: {}", err);
+                std::process::exit(1);
+            }
         }
     }
     Ok(())
@@ -94,11 +510,11 @@ pub enum ClientEvent {
     ServerMessage(message::ToClientMsg),
 }
 
-async fn run_client(addr: &str, username: Username) -> client::error::Result<()> {
+async fn run_client(addr: &str, join_request: JoinRequest) -> client::error::Result<()> {
     let (mut client_evt_send, client_evt_recv) = tokio::sync::mpsc::channel::<ClientEvent>(1);
 
     let mut app =
-        ServerSession::establish_connection(addr, username, client_evt_send.clone()).await?;
+        ServerSession::establish_connection(addr, join_request, client_evt_send.clone()).await?;
 
     enable_raw_mode()?;
     execute!(stdout(), EnterAlternateScreen)?;
@@ -1,27 +1,258 @@
-use crate::{data, server::skribbl::SkribblState};
-use serde::{Deserialize, Serialize};
+use crate::{
+    data,
+    server::{lobby::LobbyState, skribbl::SkribblState},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+
+/// version of the protocol, sent in the join request and as the first byte of every binary message.
+/// The server turns away clients with a different version.
+///
+/// Bump this whenever a change would make an older client or server misread a message:
+/// adding, removing, reordering or changing a variant of ToClientMsg or ToServerMsg,
+/// or changing any type they contain. Adding a field with a serde default to JoinRequest
+/// doesn't need a bump, as JoinRequest is always JSON.
+pub const PROTOCOL_VERSION: u8 = 44;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ToClientMsg {
-    NewMessage(data::Message),
+    NewMessage(data::TimedMessage),
     NewLine(data::Line),
+    /// several lines drawn shortly after each other. The server sends these instead of single lines
+    NewLines(Vec<data::Line>),
+    Fill(data::Fill),
+    Shape(data::Shape),
+    /// the last line, fill or shape was taken back, and should be removed from the canvas
+    UndoLine,
+    /// sent only to the user who drew this line, fill or shape, as the server didn't take it,
+    /// e.g. because the canvas is full. It should be removed from their canvas
+    CanvasOpRejected(data::CanvasOp),
     InitialState(InitialState),
     SkribblStateChanged(SkribblState),
-    GameOver(SkribblState),
+    /// every player with their score, best first. Sent whenever a score changes,
+    /// and whenever a player joins or leaves the game
+    Scoreboard(Vec<(data::Username, u32)>),
+    /// someone got ready or left while the room is waiting for the game to start
+    LobbyStateChanged(LobbyState),
+    /// the last round is over. Contains every player's score, best first.
+    /// The room goes back to the lobby afterwards
+    GameOver {
+        scores: Vec<(data::Username, u32)>,
+    },
+    /// the client was removed from the room, for the given reason. The connection is closed right after
+    Kicked(String),
+    /// the host stopped the game or left the lobby. The room is for drawing only from now on
+    GameStopped,
+    /// the canvas got a new size, so every player can show all of it. Replaces the canvas with
+    /// what was drawn on it, where lines are moved onto the new canvas and fills outside of it are left out
+    DimensionsChanged {
+        dimensions: (usize, usize),
+        canvas_ops: Vec<data::CanvasOp>,
+    },
     ClearCanvas,
-    TimeChanged(u32),
+    /// the clock of the current turn, sent on every tick. Clients can count down smoothly
+    /// between ticks by comparing the two timestamps, without relying on their own clock
+    TimeChanged {
+        /// whole seconds left in the turn
+        remaining_secs: u32,
+        /// the server's clock when the message was sent, in milliseconds since the unix epoch
+        server_time: u64,
+        /// when the turn ends on the server's clock, in milliseconds since the unix epoch.
+        /// None while the game is paused
+        round_end: Option<u64>,
+    },
+    /// sent only to the drawing user, who has to pick one of the words
+    ChooseWord(Vec<String>),
+    /// the current word with only the revealed letters shown, sent to everyone but the drawing user
+    WordHint(String),
+    /// a turn ended, and this player draws next once the break between turns is over
+    NextDrawer(data::Username),
+    /// a turn started, as the drawing user chose the word. The word only shows its length,
+    /// with an underscore for every letter
+    RoundStarted {
+        round: u32,
+        drawer: data::Username,
+        word: String,
+    },
+    /// sent only to the drawing user once their turn starts, with the word they have to draw
+    YourTurn {
+        word: String,
+    },
+    /// how many spectators are watching the room
+    SpectatorCount(usize),
+    /// everyone in the room, ordered by name. Sent in every mode whenever someone joins or leaves
+    PlayerList(Vec<data::PlayerListEntry>),
+    /// how many players guessed the word so far, out of everyone but the drawing user.
+    /// Sent whenever someone guesses it
+    GuessProgress {
+        solved: usize,
+        total: usize,
+    },
+    /// someone else started or stopped typing a message
+    UserTyping(data::Username, bool),
+    /// the server is shutting down, and will close the connection right after
+    ServerShutdown,
+    /// a secret to send in the JoinRequest when rejoining after the connection dropped,
+    /// proving that the player is the one who left
+    ReconnectToken(String),
+    /// one edit of the drawing the client asked to replay, sent when it was originally drawn
+    ReplayFrame(data::ReplayFrame),
+    /// the replay is over, there are no more frames
+    ReplayEnded,
+    /// sent instead of InitialState if the server didn't accept the join attempt
+    JoinRejected(String),
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ToServerMsg {
     NewMessage(data::Message),
     CommandMsg(data::CommandMsg),
     NewLine(data::Line),
+    Fill(data::Fill),
+    Shape(data::Shape),
+    /// take back the last line, fill or shape. Only the drawing user may do this in a skribbl game
+    UndoLine,
     ClearCanvas,
+    WordChosen(String),
+    /// the player is ready for the game to start
+    Ready,
+    /// a private message to a single player
+    Whisper {
+        to: data::Username,
+        text: String,
+    },
+    /// whether the player is writing a message. Has to be repeated while they keep typing
+    Typing(bool),
+}
+
+/// the first message a client sends after connecting
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JoinRequest {
+    /// the PROTOCOL_VERSION of the client. Clients from before the version existed send none
+    #[serde(default)]
+    pub protocol_version: u8,
+    pub username: String,
+    pub room: data::RoomId,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// how every following message is encoded, in both directions
+    #[serde(default)]
+    pub codec: Codec,
+    /// spectators see everything the players see, but can't draw, chat or guess
+    #[serde(default)]
+    pub spectator: bool,
+    /// makes the client the host of the room, if it matches the host key of the server
+    #[serde(default)]
+    pub host_key: Option<String>,
+    /// the ReconnectToken the client got before its connection dropped, to get its place back
+    #[serde(default)]
+    pub reconnect_token: Option<String>,
+    /// the biggest canvas the client can show, as width and height
+    #[serde(default)]
+    pub dimensions: Option<(usize, usize)>,
+}
+
+/// the encodings a client can ask for in its join request.
+/// The join request itself, and a rejection of it, are always JSON.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    /// bincode, prefixed with the PROTOCOL_VERSION byte
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// a binary message was encoded with a different protocol version than ours
+    VersionMismatch(u8),
+    Invalid(String),
+}
+
+/// a message that couldn't be serialized
+#[derive(Debug, Clone)]
+pub struct EncodeError(pub String);
+
+impl Codec {
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<tungstenite::Message, EncodeError> {
+        match self {
+            Codec::Json => serde_json::to_string(msg)
+                .map(tungstenite::Message::Text)
+                .map_err(|err| EncodeError(err.to_string())),
+            Codec::Binary => {
+                let mut bytes = vec![PROTOCOL_VERSION];
+                bincode::serialize_into(&mut bytes, msg)
+                    .map_err(|err| EncodeError(err.to_string()))?;
+                Ok(tungstenite::Message::Binary(bytes))
+            }
+        }
+    }
+}
+
+/// a message for any number of clients. It's serialized at most once per codec,
+/// by whichever client needs it first, and every other client gets a copy of that frame
+#[derive(Debug)]
+pub struct SharedMsg<T> {
+    msg: T,
+    json: OnceLock<Result<tungstenite::Message, EncodeError>>,
+    binary: OnceLock<Result<tungstenite::Message, EncodeError>>,
+}
+
+impl<T: Serialize> SharedMsg<T> {
+    pub fn new(msg: T) -> Self {
+        SharedMsg {
+            msg,
+            json: OnceLock::new(),
+            binary: OnceLock::new(),
+        }
+    }
+
+    pub fn encode(&self, codec: Codec) -> Result<tungstenite::Message, EncodeError> {
+        let encoded = match codec {
+            Codec::Json => &self.json,
+            Codec::Binary => &self.binary,
+        };
+        encoded.get_or_init(|| codec.encode(&self.msg)).clone()
+    }
+}
+
+impl From<ToClientMsg> for Arc<SharedMsg<ToClientMsg>> {
+    fn from(msg: ToClientMsg) -> Self {
+        Arc::new(SharedMsg::new(msg))
+    }
+}
+
+/// decodes a text or binary websocket message, whichever codec it was encoded with.
+/// Returns None for any other kind of message, like pings.
+pub fn decode_msg<T: DeserializeOwned>(
+    msg: &tungstenite::Message,
+) -> Option<Result<T, DecodeError>> {
+    match msg {
+        tungstenite::Message::Text(text) => {
+            Some(serde_json::from_str(text).map_err(|err| DecodeError::Invalid(err.to_string())))
+        }
+        tungstenite::Message::Binary(bytes) => match bytes.split_first() {
+            Some((&PROTOCOL_VERSION, payload)) => Some(
+                bincode::deserialize(payload).map_err(|err| DecodeError::Invalid(err.to_string())),
+            ),
+            Some((&version, _)) => Some(Err(DecodeError::VersionMismatch(version))),
+            None => Some(Err(DecodeError::Invalid("empty message".to_string()))),
+        },
+        _ => None,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InitialState {
-    pub lines: Vec<data::Line>,
+    /// everything drawn on the canvas so far, in order
+    pub canvas_ops: Vec<data::CanvasOp>,
     pub dimensions: (usize, usize),
+    /// the colors the server's players draw with
+    pub palette: Vec<data::CanvasColor>,
+    /// the latest chat messages, oldest first
+    pub chat_history: Vec<data::TimedMessage>,
     pub skribbl_state: Option<SkribblState>,
+    /// set if the room is waiting for a game to start
+    pub lobby_state: Option<LobbyState>,
+    /// the color the user is shown in, which is also what they start drawing with
+    pub color: data::CanvasColor,
 }
@@ -0,0 +1,103 @@
+use crate::data::{CanvasColor, CanvasOp, Coord, Fill};
+use image::{DynamicImage, ImageOutputFormat, ImageResult, Rgb, RgbImage};
+
+/// how many pixels wide and high a canvas cell is in an image.
+/// Terminal cells are about twice as high as they are wide
+pub const CELL_WIDTH: u32 = 8;
+pub const CELL_HEIGHT: u32 = 16;
+
+/// cells nothing was drawn on are black, like the background of most terminals
+pub const BACKGROUND: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// replays the canvas ops into the color of every cell, indexed by [y][x].
+/// Cells nothing was drawn on are None.
+pub fn rasterize(
+    ops: &[CanvasOp],
+    (width, height): (usize, usize),
+) -> Vec<Vec<Option<CanvasColor>>> {
+    let mut cells = vec![vec![None; width]; height];
+    for op in ops.iter() {
+        paint(&mut cells, op);
+    }
+    cells
+}
+
+/// applies a canvas op to the cells, indexed by [y][x]. Returns the (x, y) of every cell it colored
+pub fn paint(cells: &mut [Vec<Option<CanvasColor>>], op: &CanvasOp) -> Vec<(usize, usize)> {
+    match op {
+        CanvasOp::Line(line) => {
+            let mut painted = Vec::new();
+            for Coord(x, y) in line.coords_in() {
+                let (x, y) = (x as usize, y as usize);
+                if let Some(cell) = cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    *cell = Some(line.color);
+                    painted.push((x, y));
+                }
+            }
+            painted
+        }
+        CanvasOp::Fill(fill) => flood_fill(cells, fill),
+        CanvasOp::Shape(shape) => {
+            let mut painted = Vec::new();
+            for Coord(x, y) in shape.coords_in() {
+                let (x, y) = (x as usize, y as usize);
+                if let Some(cell) = cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    *cell = Some(shape.color);
+                    painted.push((x, y));
+                }
+            }
+            painted
+        }
+    }
+}
+
+/// colors the cells connected to the fill's position that have the same color as it.
+/// The outermost cells are the canvas border, so the fill never spreads over them.
+fn flood_fill(cells: &mut [Vec<Option<CanvasColor>>], fill: &Fill) -> Vec<(usize, usize)> {
+    let height = cells.len();
+    let width = cells.first().map_or(0, |row| row.len());
+    let is_inside = |x: usize, y: usize| x > 0 && y > 0 && x + 1 < width && y + 1 < height;
+
+    let (x, y) = (fill.x as usize, fill.y as usize);
+    if !is_inside(x, y) {
+        return Vec::new();
+    }
+    let target = cells[y][x];
+    if target == Some(fill.color) {
+        return Vec::new();
+    }
+
+    let mut painted = Vec::new();
+    let mut stack = vec![(x, y)];
+    while let Some((x, y)) = stack.pop() {
+        if !is_inside(x, y) || cells[y][x] != target {
+            continue;
+        }
+        cells[y][x] = Some(fill.color);
+        painted.push((x, y));
+        stack.extend_from_slice(&[(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]);
+    }
+    painted
+}
+
+/// the canvas as an image. An empty canvas gives a blank image of the canvas' size
+pub fn to_image(ops: &[CanvasOp], dimensions: (usize, usize)) -> RgbImage {
+    let (width, height) = dimensions;
+    let cells = rasterize(ops, dimensions);
+    RgbImage::from_fn(
+        width as u32 * CELL_WIDTH,
+        height as u32 * CELL_HEIGHT,
+        |x, y| {
+            let cell = cells[(y / CELL_HEIGHT) as usize][(x / CELL_WIDTH) as usize];
+            cell.map_or(BACKGROUND, |color| Rgb(color.rgb()))
+        },
+    )
+}
+
+/// encodes the canvas as a PNG image
+pub fn to_png(ops: &[CanvasOp], dimensions: (usize, usize)) -> ImageResult<Vec<u8>> {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(to_image(ops, dimensions))
+        .write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png)
+}
@@ -0,0 +1,50 @@
+use crate::data::Username;
+use std::{collections::HashMap, net::IpAddr, path::PathBuf};
+
+/// the addresses that may not connect anymore, with the player each one was banned for.
+/// Without a file they only last as long as the server runs
+#[derive(Debug, Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: HashMap<IpAddr, Username>,
+}
+
+impl BanList {
+    /// reads the bans from the given JSON file. A file that doesn't exist yet counts as empty
+    pub fn load(path: Option<PathBuf>) -> std::io::Result<Self> {
+        let bans = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => HashMap::new(),
+        };
+        Ok(BanList { path, bans })
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.bans.contains_key(&ip)
+    }
+
+    /// bans the address and writes the bans to the file, if there is one
+    pub fn ban(&mut self, ip: IpAddr, username: Username) -> std::io::Result<()> {
+        self.bans.insert(ip, username);
+        self.save()
+    }
+
+    /// lifts every ban of the player and writes the bans to the file, if there is one.
+    /// Returns whether the player was banned at all
+    pub fn unban(&mut self, username: &Username) -> std::io::Result<bool> {
+        let ban_cnt = self.bans.len();
+        self.bans.retain(|_, banned| banned != username);
+        if self.bans.len() == ban_cnt {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string_pretty(&self.bans)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+use super::{
+    server::{
+        ServerError, TlsFiles, DEFAULT_PORT, MAX_LINES, MAX_LINE_RATE, MAX_MESSAGE_LENGTH,
+        PONG_TIMEOUT, RECONNECT_GRACE_PERIOD, SESSION_BUFFER, SLOW_CLIENT_TIMEOUT, TICK_INTERVAL,
+    },
+    skribbl::{GameSettings, ScoringSettings},
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// everything `run_server` needs to know. Start from the default, or from `ServerConfig::builder()`
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// the address the websocket listener binds to, an IP address and a port like `0.0.0.0:8888`
+    /// or `[::]:8888`. The metrics, health checks and canvases are served on the same IP address
+    pub addr: String,
+    pub dimensions: (usize, usize),
+    /// files or http(s) URLs with the words to draw. Without any, rooms are only for drawing
+    pub word_sources: Vec<PathBuf>,
+    /// the rules new games are started with
+    pub settings: GameSettings,
+    /// if set, a room accepts at most this many players. Spectators don't count
+    pub max_players: Option<usize>,
+    /// if set, clients have to send this password to join
+    pub password: Option<String>,
+    /// if set, a client sending this key becomes the host of its room
+    pub host_key: Option<String>,
+    /// if set, the server only accepts wss connections
+    pub tls_files: Option<TlsFiles>,
+    /// connections that don't answer pings for this long are dropped
+    pub pong_timeout: Duration,
+    /// how long a player whose connection dropped keeps their place in the game, waiting for them to reconnect
+    pub reconnect_grace_period: Duration,
+    /// how often the game clock is updated. Shorter intervals make the countdown smoother,
+    /// but cost more CPU time. The clock is only sent to the clients when the remaining seconds change
+    pub tick_interval: Duration,
+    /// how many messages may wait to be sent to a client
+    pub session_buffer: usize,
+    /// clients whose buffer stays full for this long are dropped, so they don't hold up their room
+    pub slow_client_timeout: Duration,
+    /// how many lines and fills a canvas holds before it has to be cleared
+    pub max_lines: usize,
+    /// how many lines per second every client may draw
+    pub max_line_rate: u32,
+    /// how many characters a chat message may have. Join requests may only be a bit longer
+    pub max_message_length: usize,
+    /// file with words that are censored in the chat
+    pub blocklist_file: Option<PathBuf>,
+    /// JSON file the player stats are kept in
+    pub stats_file: Option<PathBuf>,
+    /// JSON file the banned addresses are kept in
+    pub ban_file: Option<PathBuf>,
+    /// file the rooms are saved to on shutdown, and restored from on startup
+    pub resume_file: Option<PathBuf>,
+    /// port to serve Prometheus metrics on
+    pub metrics_port: Option<u16>,
+    /// port to answer health checks on
+    pub health_port: Option<u16>,
+    /// port to serve the canvases of the rooms on, as PNG images. It is plain HTTP, even with tls_files,
+    /// so the password in its URLs isn't encrypted
+    pub canvas_port: Option<u16>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            addr: format!("0.0.0.0:{}", DEFAULT_PORT),
+            dimensions: (100, 50),
+            word_sources: Vec::new(),
+            settings: GameSettings::default(),
+            max_players: None,
+            password: None,
+            host_key: None,
+            tls_files: None,
+            pong_timeout: Duration::from_secs(PONG_TIMEOUT),
+            reconnect_grace_period: Duration::from_secs(RECONNECT_GRACE_PERIOD),
+            tick_interval: Duration::from_millis(TICK_INTERVAL),
+            session_buffer: SESSION_BUFFER,
+            slow_client_timeout: Duration::from_secs(SLOW_CLIENT_TIMEOUT),
+            max_lines: MAX_LINES,
+            max_line_rate: MAX_LINE_RATE,
+            max_message_length: MAX_MESSAGE_LENGTH,
+            blocklist_file: None,
+            stats_file: None,
+            ban_file: None,
+            resume_file: None,
+            metrics_port: None,
+            health_port: None,
+            canvas_port: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder::default()
+    }
+}
+
+/// builds a ServerConfig, keeping the default for everything that isn't set
+#[derive(Debug, Default)]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+}
+
+impl ServerConfigBuilder {
+    /// takes what is set in the environment, for deployments like containers where that is easier
+    /// than passing arguments: TERMIBBL_ADDR, TERMIBBL_ROUND_DURATION in seconds, TERMIBBL_WORDS
+    /// as a comma separated list, TERMIBBL_MAX_PLAYERS, TERMIBBL_PASSWORD and TERMIBBL_HOST_KEY.
+    /// Variables that aren't set or are empty keep what was set before
+    pub fn env(mut self) -> Result<Self, ServerError> {
+        if let Some(addr) = env_var("TERMIBBL_ADDR")? {
+            if addr.parse::<SocketAddr>().is_err() {
+                return Err(ServerError::InvalidEnvVar("TERMIBBL_ADDR", addr));
+            }
+            self.config.addr = addr;
+        }
+        if let Some(round_duration) = parse_env_var("TERMIBBL_ROUND_DURATION")? {
+            self.config.settings.round_duration = Duration::from_secs(round_duration);
+        }
+        if let Some(words) = env_var("TERMIBBL_WORDS")? {
+            self.config.word_sources = words
+                .split(',')
+                .map(str::trim)
+                .filter(|source| !source.is_empty())
+                .map(PathBuf::from)
+                .collect();
+        }
+        if let Some(max_players) = parse_env_var("TERMIBBL_MAX_PLAYERS")? {
+            self.config.max_players = Some(max_players);
+        }
+        if let Some(password) = env_var("TERMIBBL_PASSWORD")? {
+            self.config.password = Some(password);
+        }
+        if let Some(host_key) = env_var("TERMIBBL_HOST_KEY")? {
+            self.config.host_key = Some(host_key);
+        }
+        Ok(self)
+    }
+
+    pub fn addr(mut self, addr: impl Into<String>) -> Self {
+        self.config.addr = addr.into();
+        self
+    }
+
+    /// replaces only the IP address and the port that are given, keeping the rest of the address
+    pub fn bind(mut self, ip: Option<IpAddr>, port: Option<u16>) -> Self {
+        let mut addr = self
+            .config
+            .addr
+            .parse()
+            .unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT));
+        if let Some(ip) = ip {
+            addr.set_ip(ip);
+        }
+        if let Some(port) = port {
+            addr.set_port(port);
+        }
+        self.config.addr = addr.to_string();
+        self
+    }
+
+    pub fn dimensions(mut self, dimensions: (usize, usize)) -> Self {
+        self.config.dimensions = dimensions;
+        self
+    }
+
+    /// adds a file or http(s) URL to read words from
+    pub fn word_source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.config.word_sources.push(source.into());
+        self
+    }
+
+    pub fn word_sources(mut self, sources: Vec<PathBuf>) -> Self {
+        self.config.word_sources = sources;
+        self
+    }
+
+    pub fn settings(mut self, settings: GameSettings) -> Self {
+        self.config.settings = settings;
+        self
+    }
+
+    pub fn round_duration(mut self, round_duration: Duration) -> Self {
+        self.config.settings.round_duration = round_duration;
+        self
+    }
+
+    pub fn scoring(mut self, scoring: ScoringSettings) -> Self {
+        self.config.settings.scoring = scoring;
+        self
+    }
+
+    pub fn max_players(mut self, max_players: Option<usize>) -> Self {
+        self.config.max_players = max_players;
+        self
+    }
+
+    pub fn password(mut self, password: Option<String>) -> Self {
+        self.config.password = password;
+        self
+    }
+
+    pub fn host_key(mut self, host_key: Option<String>) -> Self {
+        self.config.host_key = host_key;
+        self
+    }
+
+    pub fn tls_files(mut self, tls_files: Option<TlsFiles>) -> Self {
+        self.config.tls_files = tls_files;
+        self
+    }
+
+    pub fn pong_timeout(mut self, pong_timeout: Duration) -> Self {
+        self.config.pong_timeout = pong_timeout;
+        self
+    }
+
+    pub fn reconnect_grace_period(mut self, reconnect_grace_period: Duration) -> Self {
+        self.config.reconnect_grace_period = reconnect_grace_period;
+        self
+    }
+
+    pub fn session_buffer(mut self, session_buffer: usize) -> Self {
+        self.config.session_buffer = session_buffer;
+        self
+    }
+
+    pub fn slow_client_timeout(mut self, slow_client_timeout: Duration) -> Self {
+        self.config.slow_client_timeout = slow_client_timeout;
+        self
+    }
+
+    pub fn tick_interval(mut self, tick_interval: Duration) -> Self {
+        self.config.tick_interval = tick_interval;
+        self
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.config.max_lines = max_lines;
+        self
+    }
+
+    pub fn max_line_rate(mut self, max_line_rate: u32) -> Self {
+        self.config.max_line_rate = max_line_rate;
+        self
+    }
+
+    pub fn max_message_length(mut self, max_message_length: usize) -> Self {
+        self.config.max_message_length = max_message_length;
+        self
+    }
+
+    pub fn blocklist_file(mut self, blocklist_file: Option<PathBuf>) -> Self {
+        self.config.blocklist_file = blocklist_file;
+        self
+    }
+
+    pub fn stats_file(mut self, stats_file: Option<PathBuf>) -> Self {
+        self.config.stats_file = stats_file;
+        self
+    }
+
+    pub fn ban_file(mut self, ban_file: Option<PathBuf>) -> Self {
+        self.config.ban_file = ban_file;
+        self
+    }
+
+    pub fn resume_file(mut self, resume_file: Option<PathBuf>) -> Self {
+        self.config.resume_file = resume_file;
+        self
+    }
+
+    pub fn metrics_port(mut self, metrics_port: Option<u16>) -> Self {
+        self.config.metrics_port = metrics_port;
+        self
+    }
+
+    pub fn health_port(mut self, health_port: Option<u16>) -> Self {
+        self.config.health_port = health_port;
+        self
+    }
+
+    pub fn canvas_port(mut self, canvas_port: Option<u16>) -> Self {
+        self.config.canvas_port = canvas_port;
+        self
+    }
+
+    pub fn build(self) -> ServerConfig {
+        self.config
+    }
+}
+
+/// the value of the environment variable, unless it isn't set or is empty
+fn env_var(name: &'static str) -> Result<Option<String>, ServerError> {
+    match std::env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(value)) => Err(ServerError::InvalidEnvVar(
+            name,
+            value.to_string_lossy().into_owned(),
+        )),
+    }
+}
+
+fn parse_env_var<T: FromStr>(name: &'static str) -> Result<Option<T>, ServerError> {
+    env_var(name)?
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| ServerError::InvalidEnvVar(name, value))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_keeps_what_isnt_given() {
+        let config = ServerConfig::builder().addr("[::1]:9000");
+        let config = config.bind(None, Some(9001)).build();
+        assert_eq!(config.addr, "[::1]:9001");
+
+        let config = ServerConfig::builder().addr("[::1]:9000");
+        let config = config.bind(Some(Ipv4Addr::LOCALHOST.into()), None).build();
+        assert_eq!(config.addr, "127.0.0.1:9000");
+    }
+}
@@ -0,0 +1,102 @@
+use futures_util::future::BoxFuture;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use std::{collections::HashMap, convert::Infallible, future::Future, net::SocketAddr, sync::Arc};
+
+/// renders a page for the parameters of the query string, returning its content type and body.
+/// None answers with 404, e.g. because the page is about something that doesn't exist
+pub type Page = Box<
+    dyn Fn(HashMap<String, String>) -> BoxFuture<'static, Option<(&'static str, Vec<u8>)>>
+        + Send
+        + Sync,
+>;
+
+/// binds a small HTTP endpoint that answers GET requests for the given paths, and 404 for anything else.
+/// The returned future serves requests until it is dropped
+pub fn bind(
+    addr: SocketAddr,
+    pages: Vec<(&'static str, Page)>,
+) -> hyper::Result<impl Future<Output = hyper::Result<()>>> {
+    let pages = Arc::new(pages);
+    let make_service = make_service_fn(move |_| {
+        let pages = pages.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let pages = pages.clone();
+                async move { Ok::<_, Infallible>(respond(&pages, &request).await) }
+            }))
+        }
+    });
+    Ok(Server::try_bind(&addr)?.serve(make_service))
+}
+
+async fn respond(pages: &[(&'static str, Page)], request: &Request<Body>) -> Response<Body> {
+    let page = pages
+        .iter()
+        .find(|(path, _)| *path == request.uri().path())
+        .filter(|_| request.method() == hyper::Method::GET);
+    let rendered = match page {
+        Some((_, render)) => render(parse_query(request.uri().query().unwrap_or(""))).await,
+        None => None,
+    };
+    let mut response = Response::new(Body::empty());
+    match rendered {
+        Some((content_type, body)) => {
+            *response.body_mut() = Body::from(body);
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        None => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+    response
+}
+
+/// the parameters of a query string like `room=a%20room&password=secret`
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+        .collect()
+}
+
+/// decodes `%XX` escapes and `+` for spaces. Invalid escapes are kept as they are
+fn percent_decode(text: &str) -> String {
+    let text = text.replace('+', " ");
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        let escaped = after
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &after[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_parameters_are_decoded() {
+        let params = parse_query("room=my%20room&password=a+b%2Bc&broken=%zz");
+        assert_eq!(params["room"], "my room");
+        assert_eq!(params["password"], "a b+c");
+        assert_eq!(params["broken"], "%zz");
+    }
+}
@@ -0,0 +1,35 @@
+use crate::data::Username;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// a room waiting for its players to get ready for a skribbl game
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LobbyState {
+    /// the players that are ready to start
+    pub ready_users: BTreeSet<Username>,
+
+    /// how many players have to be ready for the game to start on its own
+    pub min_ready_players: usize,
+}
+
+impl LobbyState {
+    pub fn new(min_ready_players: usize) -> Self {
+        LobbyState {
+            ready_users: BTreeSet::new(),
+            min_ready_players,
+        }
+    }
+
+    /// marks the user as ready. Returns false if they already were
+    pub fn set_ready(&mut self, username: Username) -> bool {
+        self.ready_users.insert(username)
+    }
+
+    pub fn remove_user(&mut self, username: &Username) {
+        self.ready_users.remove(username);
+    }
+
+    pub fn can_start(&self) -> bool {
+        self.ready_users.len() >= self.min_ready_players
+    }
+}
@@ -0,0 +1,84 @@
+use prometheus::{Encoder, IntCounter, IntGauge, Registry, TextEncoder};
+
+/// numbers about the whole server, exported in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    /// connected clients, spectators included
+    pub sessions: IntGauge,
+    pub rooms: IntGauge,
+    /// rooms with a skribbl game going on
+    pub active_games: IntGauge,
+    /// lines and fills on the canvases of all rooms
+    pub canvas_ops: IntGauge,
+    /// messages received from clients. Prometheus derives the messages per second from it
+    pub client_messages: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        let client_messages = IntCounter::new(
+            "termibbl_client_messages_total",
+            "Messages received from clients",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(client_messages.clone()))
+            .unwrap();
+        Metrics {
+            sessions: register_gauge(&registry, "termibbl_sessions", "Connected clients"),
+            rooms: register_gauge(&registry, "termibbl_rooms", "Rooms with clients in them"),
+            active_games: register_gauge(
+                &registry,
+                "termibbl_active_games",
+                "Rooms with a game going on",
+            ),
+            canvas_ops: register_gauge(
+                &registry,
+                "termibbl_canvas_ops",
+                "Lines and fills stored on all canvases",
+            ),
+            client_messages,
+            registry,
+        }
+    }
+}
+
+impl Metrics {
+    /// the metrics in the Prometheus text format, with its content type
+    pub fn encode(&self) -> (&'static str, Vec<u8>) {
+        let mut buffer = Vec::new();
+        // encoding only fails for invalid metrics, which we don't register
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        (prometheus::TEXT_FORMAT, buffer)
+    }
+}
+
+fn register_gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::new(name, help).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    gauge
+}
+
+/// what a room added to the server-wide gauges when it last reported
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RoomMetrics {
+    pub sessions: i64,
+    pub active_games: i64,
+    pub canvas_ops: i64,
+}
+
+impl RoomMetrics {
+    /// adds the difference to the last report to the gauges, and remembers the new numbers
+    pub fn report(&mut self, metrics: &Metrics, current: RoomMetrics) {
+        metrics.sessions.add(current.sessions - self.sessions);
+        metrics
+            .active_games
+            .add(current.active_games - self.active_games);
+        metrics.canvas_ops.add(current.canvas_ops - self.canvas_ops);
+        *self = current;
+    }
+}
@@ -1,2 +1,10 @@
+pub mod bans;
+pub mod config;
+pub mod http;
+pub mod lobby;
+pub mod metrics;
+pub mod recording;
 pub mod server;
 pub mod skribbl;
+pub mod snapshot;
+pub mod stats;
@@ -0,0 +1,28 @@
+use crate::data::{CanvasEdit, ReplayFrame};
+use std::time::Instant;
+
+/// how many frames a recording holds at most. Later edits aren't recorded
+const MAX_RECORDING_LENGTH: usize = 20_000;
+
+/// everything drawn on the canvas since it was last cleared, and when
+#[derive(Debug, Default)]
+pub struct Recording {
+    /// when the first edit was recorded. The wait before it isn't worth replaying
+    started_at: Option<Instant>,
+    frames: Vec<ReplayFrame>,
+}
+
+impl Recording {
+    pub fn record(&mut self, edit: CanvasEdit) {
+        if self.frames.len() < MAX_RECORDING_LENGTH {
+            let started_at = *self.started_at.get_or_insert_with(Instant::now);
+            let at = started_at.elapsed().as_millis().min(u32::MAX as u128) as u32;
+            self.frames.push(ReplayFrame { at, edit });
+        }
+    }
+
+    /// ends the recording, returning its frames
+    pub fn finish(self) -> Vec<ReplayFrame> {
+        self.frames
+    }
+}
@@ -1,23 +1,132 @@
 //https://github.com/snapview/tokio-tungstenite/blob/master/examples/server.rs
 
-use super::skribbl::SkribblState;
+use super::{
+    bans::BanList,
+    config::ServerConfig,
+    http,
+    lobby::LobbyState,
+    metrics::{Metrics, RoomMetrics},
+    recording::Recording,
+    skribbl::{get_time_now_millis, GameSettings, SkribblState, Word},
+    snapshot::{RoomSnapshot, Snapshot},
+    stats::StatsStore,
+};
 use crate::{
     data,
-    message::{InitialState, ToClientMsg, ToServerMsg},
+    message::{
+        decode_msg, Codec, DecodeError, EncodeError, InitialState, JoinRequest, SharedMsg,
+        ToClientMsg, ToServerMsg, PROTOCOL_VERSION,
+    },
+    render,
+};
+use data::{
+    CanvasColor, CanvasEdit, CanvasOp, CommandMsg, Message, PlayerListEntry, ReplayFrame, RoomId,
+    TimedMessage, Username,
+};
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read};
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use data::{CommandMsg, Message, Username};
-use futures_timer::Delay;
-use futures_util::{SinkExt, StreamExt};
-use std::io::Read;
-use std::net::SocketAddr;
-use std::{cmp::min, collections::HashMap, path::PathBuf, time::Duration};
 use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::Mutex,
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{oneshot, Mutex},
+};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        NoClientAuth, ServerConfig as TlsConfig,
+    },
+    TlsAcceptor,
 };
+use tracing::{debug, error, info, warn, Instrument};
+use tungstenite::protocol::WebSocketConfig;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 
+/// default port to listen on
+pub const DEFAULT_PORT: u16 = 8888;
+
+/// default length of a turn, in seconds
 pub const ROUND_DURATION: u64 = 120;
 
+/// default milliseconds between two updates of the game clock
+pub const TICK_INTERVAL: u64 = 500;
+
+/// players count as typing for this long after they last said they are
+const TYPING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// the smallest canvas clients can make the room use
+const MIN_DIMENSIONS: (usize, usize) = (40, 20);
+
+/// default number of lines and fills the canvas holds before further drawing is rejected
+pub const MAX_LINES: usize = 5000;
+
+/// how many of the latest chat messages new players get to see
+const CHAT_HISTORY_LENGTH: usize = 50;
+
+/// how long new lines are collected before they are sent to the clients together
+const LINE_BATCH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// how often the server pings every client
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// default number of lines a client may draw per second. Lines beyond that are dropped
+pub const MAX_LINE_RATE: u32 = 200;
+
+/// default number of characters a chat message may have
+pub const MAX_MESSAGE_LENGTH: usize = 500;
+
+/// how many bytes a join request may have besides the message length, for the other fields
+const JOIN_REQUEST_OVERHEAD: usize = 512;
+
+/// how many bytes a character of a message may take up in a websocket frame, with JSON escapes
+const MAX_BYTES_PER_CHAR: usize = 6;
+
+/// how many chat messages a client may send within CHAT_RATE_PERIOD before being muted
+const CHAT_RATE_LIMIT: u32 = 5;
+const CHAT_RATE_PERIOD: Duration = Duration::from_secs(3);
+
+/// how long a client that sent too many chat messages is muted
+const CHAT_MUTE_DURATION: Duration = Duration::from_secs(10);
+
+/// how long downloading a word list may take before the server gives up on starting
+const WORD_LIST_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how long a vote to kick a player counts
+const KICK_VOTE_DURATION: Duration = Duration::from_secs(120);
+
+/// how often the player stats are written to their file
+const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// how many characters a reconnect token has
+const RECONNECT_TOKEN_LENGTH: usize = 24;
+
+/// how long the server waits for the connections to close when shutting down
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+/// default time a client has to answer a ping before it is treated as disconnected, in seconds
+pub const PONG_TIMEOUT: u64 = 30;
+
+/// default time a player whose connection dropped may reconnect without losing their place, in seconds
+pub const RECONNECT_GRACE_PERIOD: u64 = 30;
+
+/// default number of messages that may wait to be sent to a client
+pub const SESSION_BUFFER: usize = 64;
+
+/// default time a client's message buffer may stay full before the client is dropped, in seconds
+pub const SLOW_CLIENT_TIMEOUT: u64 = 5;
+
 type Result<T> = std::result::Result<T, ServerError>;
 
 #[derive(Debug)]
@@ -26,6 +135,71 @@ pub enum ServerError {
     SendError(String),
     WsError(tungstenite::error::Error),
     IOError(std::io::Error),
+    TlsError(String),
+    /// one of the word files couldn't be read
+    WordFileError(PathBuf, std::io::Error),
+    /// one of the word lists couldn't be downloaded from its URL
+    WordListFetchError(String, reqwest::Error),
+    /// a message couldn't be serialized
+    EncodeError(String),
+    /// the HTTP endpoint for metrics or health checks couldn't be started
+    HttpError(hyper::Error),
+    /// the address to listen on isn't an IP address with a port
+    InvalidAddress(String),
+    /// the server couldn't listen on the address, e.g. because it's in use
+    BindError(SocketAddr, std::io::Error),
+    /// an environment variable the server is configured with has a value it can't use
+    InvalidEnvVar(&'static str, String),
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::UserNotFound(username) => write!(f, "user {} not found", username),
+            ServerError::SendError(err) => write!(f, "could not send a message: {}", err),
+            ServerError::WsError(err) => write!(f, "websocket error: {}", err),
+            ServerError::IOError(err) => write!(f, "IO error: {}", err),
+            ServerError::TlsError(err) => write!(f, "TLS error: {}", err),
+            ServerError::WordFileError(path, err) => {
+                write!(f, "could not read {}: {}", path.display(), err)
+            }
+            ServerError::WordListFetchError(url, err) => {
+                write!(f, "could not download {}: {}", url, err)
+            }
+            ServerError::EncodeError(err) => write!(f, "could not encode a message: {}", err),
+            ServerError::HttpError(err) => write!(f, "could not serve HTTP: {}", err),
+            ServerError::InvalidAddress(addr) => write!(f, "{} is not a valid address", addr),
+            ServerError::BindError(addr, err) => write!(f, "could not listen on {}: {}", addr, err),
+            ServerError::InvalidEnvVar(name, value) => {
+                write!(f, "{} is not a valid value for {}", value, name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ServerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServerError::WsError(err) => Some(err),
+            ServerError::IOError(err)
+            | ServerError::WordFileError(_, err)
+            | ServerError::BindError(_, err) => Some(err),
+            ServerError::WordListFetchError(_, err) => Some(err),
+            ServerError::HttpError(err) => Some(err),
+            ServerError::UserNotFound(_)
+            | ServerError::SendError(_)
+            | ServerError::TlsError(_)
+            | ServerError::EncodeError(_)
+            | ServerError::InvalidAddress(_)
+            | ServerError::InvalidEnvVar(_, _) => None,
+        }
+    }
+}
+
+impl From<EncodeError> for ServerError {
+    fn from(err: EncodeError) -> Self {
+        ServerError::EncodeError(err.0)
+    }
 }
 
 impl<T> From<tokio::sync::mpsc::error::SendError<T>> for ServerError {
@@ -46,31 +220,107 @@ impl From<std::io::Error> for ServerError {
     }
 }
 
+/// the certificate chain and private key the server uses to accept wss connections, both PEM encoded
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+/// why a join attempt was rejected
+#[derive(Debug)]
+enum JoinRejection {
+    /// the client may try again on the same connection, e.g. with a different name
+    Retry(String),
+    /// the connection is closed after telling the client why
+    Close(String),
+}
+
+/// the server's answer to a join attempt
+type JoinResult = std::result::Result<(), JoinRejection>;
+
+/// a client that wants to join a room, and where to send the server's answer to
+#[derive(Debug)]
+struct JoinAttempt {
+    session: UserSession,
+    request: JoinRequest,
+    join_send: oneshot::Sender<JoinResult>,
+}
+
 #[derive(Debug)]
 enum ServerEvent {
     ToServerMsg(Username, ToServerMsg),
-    UserJoined(UserSession),
-    UserLeft(Username),
-    Tick,
+    UserJoined(Box<JoinAttempt>),
+    /// a client's connection closed. The room drops the sender once the user is gone for good,
+    /// and until then the connection keeps counting as a member of the room
+    UserLeft(Username, oneshot::Sender<()>),
+    /// the server is shutting down. Once everyone was told, the room answers with what should survive a restart
+    Shutdown(oneshot::Sender<RoomSnapshot>),
+    /// someone wants to see the canvas. The room answers with its dimensions and what was drawn on it
+    CanvasRequested(oneshot::Sender<((usize, usize), Vec<CanvasOp>)>),
 }
 
 #[derive(Debug)]
 struct UserSession {
     username: Username,
-    msg_send: Mutex<tokio::sync::mpsc::Sender<ToClientMsg>>,
+    /// messages are shared between all the sessions they're broadcast to, and serialized only once
+    msg_send: Mutex<tokio::sync::mpsc::Sender<Arc<SharedMsg<ToClientMsg>>>>,
     close_send: tokio::sync::mpsc::Sender<()>,
+    /// when the user last sent anything to the server
+    last_activity: Instant,
+    /// when the user last drew or chatted. A drawing user who does neither for a while is idle
+    last_drawing_activity: Instant,
+    /// limits how many lines the user may draw
+    line_limiter: RateLimiter,
+    /// spectators get every message, but everything they send is ignored
+    spectator: bool,
+    /// whether the user was already told that their lines are being dropped
+    warned_about_line_rate: bool,
+    /// limits how many chat messages the user may send
+    chat_limiter: RateLimiter,
+    /// until when the user's chat messages are dropped, after they sent too many
+    muted_until: Option<Instant>,
+    /// how long sending a message may wait for room in the buffer
+    slow_client_timeout: Duration,
+    /// lets the player get their place back if the connection drops
+    reconnect_token: String,
+    /// the address the client connected from, to ban it
+    ip: IpAddr,
+    /// the biggest canvas the client can show
+    dimensions: Option<(usize, usize)>,
+    /// set once the client didn't take any messages for longer than the slow client timeout.
+    /// Nothing is sent to it anymore, and the room removes it
+    too_slow: AtomicBool,
 }
 
 impl UserSession {
     fn new(
         username: Username,
-        msg_send: tokio::sync::mpsc::Sender<ToClientMsg>,
+        msg_send: tokio::sync::mpsc::Sender<Arc<SharedMsg<ToClientMsg>>>,
         close_send: tokio::sync::mpsc::Sender<()>,
+        spectator: bool,
+        ip: IpAddr,
+        config: &ServerConfig,
     ) -> Self {
         UserSession {
             username,
+            ip,
             msg_send: Mutex::new(msg_send),
             close_send,
+            spectator,
+            last_activity: Instant::now(),
+            last_drawing_activity: Instant::now(),
+            line_limiter: RateLimiter::new(config.max_line_rate, Duration::from_secs(1)),
+            warned_about_line_rate: false,
+            chat_limiter: RateLimiter::new(CHAT_RATE_LIMIT, CHAT_RATE_PERIOD),
+            muted_until: None,
+            slow_client_timeout: config.slow_client_timeout,
+            reconnect_token: rand::thread_rng()
+                .sample_iter(&rand::distributions::Alphanumeric)
+                .take(RECONNECT_TOKEN_LENGTH)
+                .collect(),
+            dimensions: None,
+            too_slow: AtomicBool::new(false),
         }
     }
 
@@ -79,22 +329,94 @@ impl UserSession {
         Ok(())
     }
 
-    async fn send(&self, msg: ToClientMsg) -> Result<()> {
-        self.msg_send.lock().await.send(msg.clone()).await?;
+    /// queues the message for the client. If the client's buffer stays full for too long,
+    /// the client is marked as too slow instead, so it doesn't hold up the rest of the room
+    async fn send(&self, msg: impl Into<Arc<SharedMsg<ToClientMsg>>>) -> Result<()> {
+        if self.too_slow.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut msg_send = self.msg_send.lock().await;
+        match tokio::time::timeout(self.slow_client_timeout, msg_send.send(msg.into())).await {
+            Ok(result) => result?,
+            Err(_) => {
+                warn!(username = %self.username, "client is too slow, dropping it");
+                self.too_slow.store(true, Ordering::Relaxed);
+            }
+        }
         Ok(())
     }
+
+    fn is_too_slow(&self) -> bool {
+        self.too_slow.load(Ordering::Relaxed)
+    }
+}
+
+/// a player whose connection dropped, who may still come back
+#[derive(Debug)]
+struct DisconnectedPlayer {
+    disconnected_at: Instant,
+    /// the token the player has to send when rejoining
+    reconnect_token: String,
+    /// keeps the room open while the player may still reconnect, even if nobody else is left.
+    /// Dropping it lets the room close
+    _membership: oneshot::Sender<()>,
 }
 
+/// a token bucket that allows `capacity` actions per `period` on average,
+/// with bursts of up to `capacity` actions
 #[derive(Debug)]
+struct RateLimiter {
+    capacity: f64,
+    /// how many tokens are added back per second
+    refill_rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(capacity: u32, period: Duration) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            refill_rate: capacity as f64 / period.as_secs_f64(),
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// takes a token if there is one left, returning whether the action is allowed
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.last_refill).as_secs_f64() * self.refill_rate;
+        self.tokens = (self.tokens + refilled).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GameState {
     FreeDraw,
-    Skribbl(SkribblState),
+    /// waiting for players to get ready for a skribbl game. Everyone may draw meanwhile
+    Lobby(LobbyState),
+    Skribbl(Box<SkribblState>),
 }
 
 impl GameState {
     fn skribbl_state(&self) -> Option<&SkribblState> {
         match self {
-            GameState::Skribbl(state) => Some(state),
+            GameState::Skribbl(state) => Some(state.as_ref()),
+            _ => None,
+        }
+    }
+
+    fn lobby_state(&self) -> Option<&LobbyState> {
+        match self {
+            GameState::Lobby(state) => Some(state),
             _ => None,
         }
     }
@@ -103,202 +425,1568 @@ impl GameState {
 #[derive(Debug)]
 struct ServerState {
     sessions: HashMap<Username, UserSession>,
-    pub lines: Vec<data::Line>,
+    /// the lines, fills and shapes as they were drawn. While the canvas is smaller than it was
+    /// back then, clients only get them moved onto it, so the drawing comes back once it grows again
+    pub canvas_ops: Vec<data::CanvasOp>,
     pub dimensions: (usize, usize),
+    /// the configured size of the canvas. It's smaller while a client can't show all of it
+    max_dimensions: (usize, usize),
     pub game_state: GameState,
-    pub words: Option<Vec<String>>,
+    pub words: Option<Vec<Word>>,
+    /// the files and URLs the words were read from
+    word_sources: Vec<PathBuf>,
+    pub settings: GameSettings,
+    /// if set, clients have to send this password to join
+    password: Option<String>,
+    /// if set, a client sending this key becomes the host of its room
+    host_key: Option<String>,
+    /// how many canvas ops the canvas holds until it is cleared
+    max_lines: usize,
+    /// how many characters a chat message or whisper may have
+    max_message_length: usize,
+    /// if set, the room turns away players beyond this many
+    max_players: Option<usize>,
+    /// what was drawn since the canvas was last cleared
+    recording: Recording,
+    /// the drawing from before the canvas was last cleared, for replays
+    last_recording: Vec<ReplayFrame>,
+    /// lines that were drawn but not sent to the clients yet, together with who drew them
+    pending_lines: Vec<(Username, data::Line)>,
+    /// the latest chat messages everyone got, oldest first
+    chat_history: std::sync::Mutex<VecDeque<TimedMessage>>,
+    /// the player who may start the game from the lobby
+    host: Option<Username>,
+    /// the color of everyone in the room. Players that may still reconnect keep theirs
+    user_colors: HashMap<Username, CanvasColor>,
+    /// players whose connection dropped, and when. They keep their place in the game
+    /// until the grace period is over, so they can reconnect without losing their score
+    disconnected_players: HashMap<Username, DisconnectedPlayer>,
+    reconnect_grace_period: Duration,
+    /// how often the game clock is updated and sent to the clients
+    tick_interval: Duration,
+    /// words that are replaced with asterisks in chat messages
+    blocklist: Vec<String>,
+    /// players the host muted. Their chat messages are dropped, even if they rejoin
+    muted_players: HashSet<Username>,
+    /// whether the host paused the game. It then only continues once the host lets it
+    paused_by_host: bool,
+    /// who every voter voted to kick, and when. Every player has at most one vote
+    kick_votes: HashMap<Username, (Username, Instant)>,
+    /// the players that are typing, and when they last said so
+    typing: HashMap<Username, Instant>,
+    /// the remaining seconds and the end of the turn last sent in TimeChanged
+    last_time_changed: Option<(u32, Option<u64>)>,
+    /// the stats of every player on the server, shared by all rooms
+    stats: Arc<std::sync::Mutex<StatsStore>>,
+    /// the banned addresses, shared by all rooms
+    bans: Arc<std::sync::Mutex<BanList>>,
+    metrics: Arc<Metrics>,
+    /// what this room last added to the metrics
+    reported_metrics: RoomMetrics,
 }
 
 impl ServerState {
-    fn new(game_state: GameState, dimensions: (usize, usize), words: Option<Vec<String>>) -> Self {
+    fn new(
+        game_state: GameState,
+        words: Option<Vec<Word>>,
+        config: &ServerConfig,
+        blocklist: Vec<String>,
+        stats: Arc<std::sync::Mutex<StatsStore>>,
+        bans: Arc<std::sync::Mutex<BanList>>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         ServerState {
             sessions: HashMap::new(),
-            lines: Vec::new(),
-            dimensions,
+            canvas_ops: Vec::new(),
+            dimensions: config.dimensions,
+            max_dimensions: config.dimensions,
             game_state,
             words,
+            word_sources: config.word_sources.clone(),
+            settings: config.settings.clone(),
+            password: config.password.clone(),
+            host_key: config.host_key.clone(),
+            max_lines: config.max_lines,
+            max_message_length: config.max_message_length,
+            max_players: config.max_players,
+            recording: Recording::default(),
+            last_recording: Vec::new(),
+            pending_lines: Vec::new(),
+            chat_history: std::sync::Mutex::new(VecDeque::new()),
+            host: None,
+            user_colors: HashMap::new(),
+            disconnected_players: HashMap::new(),
+            reconnect_grace_period: config.reconnect_grace_period,
+            // the ticker can't run without any time between ticks
+            tick_interval: config.tick_interval.max(Duration::from_millis(1)),
+            blocklist,
+            muted_players: HashSet::new(),
+            paused_by_host: false,
+            kick_votes: HashMap::new(),
+            typing: HashMap::new(),
+            last_time_changed: None,
+            stats,
+            bans,
+            metrics,
+            reported_metrics: RoomMetrics::default(),
+        }
+    }
+
+    /// the users in the room that aren't spectators
+    fn players(&self) -> impl Iterator<Item = &Username> {
+        self.sessions
+            .values()
+            .filter(|session| !session.spectator)
+            .map(|session| &session.username)
+    }
+
+    /// a client's connection closed. Players in a lobby or game get some time to reconnect
+    /// before they are removed, everyone else is removed right away
+    async fn on_user_left(
+        &mut self,
+        username: &Username,
+        membership: oneshot::Sender<()>,
+    ) -> Result<()> {
+        let is_player = self.sessions.get(username).is_some_and(|x| !x.spectator);
+        let keeps_place = is_player
+            && !self.reconnect_grace_period.is_zero()
+            && !matches!(self.game_state, GameState::FreeDraw);
+        if !keeps_place {
+            return self.remove_player(username).await;
+        }
+        if let Some(session) = self.sessions.remove(username) {
+            let player = DisconnectedPlayer {
+                disconnected_at: Instant::now(),
+                reconnect_token: session.reconnect_token.clone(),
+                _membership: membership,
+            };
+            self.disconnected_players.insert(username.clone(), player);
+        }
+        self.broadcast_player_list().await?;
+        info!(player = %username, "waiting for player to reconnect");
+        self.broadcast_system_msg(format!("{} lost the connection", username))
+            .await
+    }
+
+    /// removes the players that didn't reconnect within the grace period
+    async fn remove_disconnected_players(&mut self) -> Result<()> {
+        let grace_period = self.reconnect_grace_period;
+        let gone_players = self
+            .disconnected_players
+            .iter()
+            .filter(|(_, player)| player.disconnected_at.elapsed() >= grace_period)
+            .map(|(username, _)| username.clone())
+            .collect::<Vec<_>>();
+        for username in gone_players {
+            self.disconnected_players.remove(&username);
+            info!(player = %username, "player didn't reconnect");
+            self.remove_player(&username).await?;
+            self.broadcast_system_msg(format!("{} left", username))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// tells the player why they have to go before removing them, so their client can tell it
+    /// apart from a dropped connection
+    async fn kick_player(&mut self, username: &Username, reason: String) -> Result<()> {
+        if let Some(session) = self.sessions.get(username) {
+            session.send(ToClientMsg::Kicked(reason)).await?;
         }
+        self.remove_player(username).await
     }
 
     async fn remove_player(&mut self, username: &Username) -> Result<()> {
+        let was_spectator = self.sessions.get(username).is_some_and(|x| x.spectator);
         self.sessions.remove(username).map(|x| x.close());
+        self.user_colors.remove(username);
+        self.kick_votes
+            .retain(|voter, (target, _)| voter != username && target != username);
+        self.set_typing(username, false).await?;
+        self.fit_canvas(None).await?;
+        self.broadcast_player_list().await?;
+        if was_spectator {
+            return self.broadcast_spectator_count().await;
+        }
+        if self.host.as_ref() == Some(username) {
+            let new_host = self.players().next().cloned();
+            self.host = new_host;
+            self.greet_host().await?;
+        }
         let state = match &mut self.game_state {
             GameState::Skribbl(state) => state,
-            _ => return Ok(()),
+            GameState::Lobby(lobby) => {
+                lobby.remove_user(username);
+                return self.broadcast_lobby_state().await;
+            }
+            GameState::FreeDraw => return Ok(()),
         };
         if state.is_drawing(username) {
-            state.next_turn();
+            self.reveal_and_advance(Some(username)).await?;
+        } else {
+            state.remove_user(username);
+            if state.is_game_over() {
+                return self.end_game().await;
+            }
+            // the turn would otherwise only end once the time is up if the player was the last one to guess
+            let (solved, _) = state.guess_progress();
+            let is_guessing = !state.is_choosing_word() && !state.is_between_turns();
+            if is_guessing && solved > 0 && state.did_all_solve() {
+                self.reveal_and_advance(None).await?;
+            }
+        }
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+        let player_cnt = self
+            .sessions
+            .values()
+            .filter(|session| !session.spectator)
+            .count();
+        let should_pause = !state.is_paused() && player_cnt < state.settings.min_players;
+        if should_pause {
+            info!(player_cnt, "pausing the game until more players join");
+            state.pause();
+        }
+        tokio::try_join!(
+            self.broadcast_skribbl_state(),
+            self.broadcast_scoreboard(),
+            self.send_word_choices()
+        )?;
+        if should_pause {
+            self.broadcast_system_msg("Waiting for more players to join...".to_string())
+                .await?;
         }
-        state.remove_user(username);
-        let state = state.clone();
-        self.broadcast(ToClientMsg::SkribblStateChanged(state))
-            .await?;
         Ok(())
     }
 
-    async fn on_command_msg(&mut self, _username: &Username, msg: &CommandMsg) -> Result<()> {
+    async fn on_command_msg(&mut self, username: &Username, msg: &CommandMsg) -> Result<()> {
+        if msg.is_privileged() && self.host.as_ref() != Some(username) {
+            let msg = Message::SystemMsg("Only the host can do that".to_string());
+            return self.send_to(username, new_message(msg)).await;
+        }
         match msg {
-            CommandMsg::KickPlayer(kicked_player) => self.remove_player(kicked_player).await?,
+            CommandMsg::KickPlayer(kicked_player) => {
+                if self.sessions.contains_key(kicked_player) {
+                    info!(host = %username, player = %kicked_player, "host kicked a player");
+                    let reason = "You were kicked by the host".to_string();
+                    self.kick_player(kicked_player, reason).await?;
+                    self.broadcast_system_msg(format!("{} was kicked by the host", kicked_player))
+                        .await?;
+                }
+            }
+            CommandMsg::BanPlayer(player) => {
+                if let Some(session) = self.sessions.get(player) {
+                    info!(host = %username, player = %player, ip = %session.ip, "host banned a player");
+                    let result = self.bans.lock().unwrap().ban(session.ip, player.clone());
+                    if let Err(err) = result {
+                        error!("could not save the bans: {}", err);
+                    }
+                    let reason = "You were banned by the host".to_string();
+                    self.kick_player(player, reason).await?;
+                    self.broadcast_system_msg(format!("{} was banned by the host", player))
+                        .await?;
+                }
+            }
+            CommandMsg::UnbanPlayer(player) => {
+                let result = self.bans.lock().unwrap().unban(player);
+                let reply = match result {
+                    Ok(true) => {
+                        info!(host = %username, player = %player, "host unbanned a player");
+                        format!("{} may join again", player)
+                    }
+                    Ok(false) => format!("{} isn't banned", player),
+                    Err(err) => {
+                        error!("could not save the bans: {}", err);
+                        format!("{} may join again, until the server restarts", player)
+                    }
+                };
+                self.send_to(username, new_message(Message::SystemMsg(reply)))
+                    .await?;
+            }
+            CommandMsg::MutePlayer(player) => {
+                if self.sessions.contains_key(player) && self.muted_players.insert(player.clone()) {
+                    self.broadcast_system_msg(format!("{} was muted by the host", player))
+                        .await?;
+                }
+            }
+            CommandMsg::UnmutePlayer(player) => {
+                if self.muted_players.remove(player) {
+                    self.broadcast_system_msg(format!("{} was unmuted by the host", player))
+                        .await?;
+                }
+            }
+            CommandMsg::VoteKick(target) => self.on_vote_kick(username, target).await?,
+            CommandMsg::VoteSkip => self.on_vote_skip(username).await?,
+            CommandMsg::StartGame => {
+                if self.game_state.lobby_state().is_some() {
+                    self.start_game().await?;
+                }
+            }
+            CommandMsg::StopGame => self.stop_game(username).await?,
+            CommandMsg::ReloadWords => self.reload_words(username).await?,
+            CommandMsg::AddWord(word) => self.add_word(username, word).await?,
+            CommandMsg::RemoveWord(word) => self.remove_word(username, word).await?,
+            CommandMsg::RestartGame => {
+                if self.words.is_some() {
+                    info!(host = %username, "host restarted the game");
+                    self.start_game().await?;
+                } else {
+                    let msg = Message::SystemMsg("There are no words to play with".to_string());
+                    self.send_to(username, new_message(msg)).await?;
+                }
+            }
+            CommandMsg::TogglePause => self.toggle_pause().await?,
+            CommandMsg::ReplayDrawing => self.replay_drawing(username).await?,
+            CommandMsg::SetWord(word) => self.set_word(username, word).await?,
+            CommandMsg::ResetScores => match &mut self.game_state {
+                GameState::Skribbl(state) => {
+                    info!(host = %username, "host reset the scores");
+                    state.reset_scores();
+                    tokio::try_join!(
+                        self.broadcast_skribbl_state(),
+                        self.broadcast_scoreboard(),
+                        self.broadcast_system_msg("The host reset the scores".to_string()),
+                    )?;
+                }
+                _ => {
+                    let msg = Message::SystemMsg("There is no game going on".to_string());
+                    self.send_to(username, new_message(msg)).await?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// replaces the word of the current turn with the one the host picked
+    async fn set_word(&mut self, host: &Username, word: &str) -> Result<()> {
+        let word = word.trim();
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => {
+                let msg = Message::SystemMsg("There is no game going on".to_string());
+                return self.send_to(host, new_message(msg)).await;
+            }
+        };
+        if word.is_empty() || !state.set_word(word.to_string()) {
+            let msg = Message::SystemMsg("The word can't be changed right now".to_string());
+            return self.send_to(host, new_message(msg)).await;
+        }
+        info!(host = %host, "host changed the word");
+        let (solved, total) = state.guess_progress();
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast_skribbl_state(),
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast(ToClientMsg::GuessProgress { solved, total }),
+            self.broadcast_system_msg("The host changed the word".to_string()),
+            self.send_your_turn(),
+        )?;
+        Ok(())
+    }
+
+    /// pauses the game until the host continues it. It only continues if there are enough players
+    async fn toggle_pause(&mut self) -> Result<()> {
+        let player_cnt = self.players().count();
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+        let msg = if !self.paused_by_host {
+            info!("host paused the game");
+            self.paused_by_host = true;
+            if !state.is_paused() {
+                state.pause();
+            }
+            "The host paused the game"
+        } else if player_cnt >= state.settings.min_players {
+            info!("host continued the game");
+            self.paused_by_host = false;
+            state.resume();
+            "The game continues!"
+        } else {
+            self.paused_by_host = false;
+            "Waiting for more players to join..."
+        };
+        tokio::try_join!(
+            self.broadcast_skribbl_state(),
+            self.broadcast_system_msg(msg.to_string()),
+        )?;
+        Ok(())
+    }
+
+    /// counts the vote, replacing the voter's earlier vote, and kicks the target once enough players agree
+    async fn on_vote_kick(&mut self, voter: &Username, target: &Username) -> Result<()> {
+        if voter == target || !self.sessions.contains_key(target) {
+            return Ok(());
+        }
+        self.kick_votes
+            .retain(|_, (_, voted_at)| voted_at.elapsed() < KICK_VOTE_DURATION);
+        self.kick_votes
+            .insert(voter.clone(), (target.clone(), Instant::now()));
+
+        let vote_cnt = self
+            .kick_votes
+            .values()
+            .filter(|(voted_for, _)| voted_for == target)
+            .count();
+        let voter_cnt = self.players().filter(|player| *player != target).count();
+        let needed_votes =
+            (voter_cnt as f64 * self.settings.vote_kick_fraction).floor() as usize + 1;
+        if vote_cnt >= needed_votes {
+            info!(player = %target, vote_cnt, "players voted to kick a player");
+            let reason = "You were kicked by vote".to_string();
+            self.kick_player(target, reason).await?;
+            self.broadcast_system_msg(format!("{} was kicked by vote", target))
+                .await
+        } else {
+            self.broadcast_system_msg(format!(
+                "{} voted to kick {} ({}/{})",
+                voter, target, vote_cnt, needed_votes
+            ))
+            .await
+        }
+    }
+
+    /// counts the vote, and ends the turn once most of the guessers voted to skip it
+    async fn on_vote_skip(&mut self, username: &Username) -> Result<()> {
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+        if state.is_choosing_word() || state.is_drawing(username) {
+            return Ok(());
+        }
+        let (vote_cnt, needed_votes) = state.vote_skip(username);
+        if vote_cnt >= needed_votes {
+            self.broadcast_system_msg("The turn was skipped".to_string())
+                .await?;
+            self.reveal_and_advance(None).await
+        } else {
+            self.broadcast_system_msg(format!(
+                "{} voted to skip the turn ({}/{})",
+                username, vote_cnt, needed_votes
+            ))
+            .await
+        }
+    }
+
+    async fn on_ready(&mut self, username: Username) -> Result<()> {
+        let lobby = match &mut self.game_state {
+            GameState::Lobby(lobby) => lobby,
+            _ => return Ok(()),
+        };
+        if !lobby.set_ready(username.clone()) {
+            return Ok(());
+        }
+        if lobby.can_start() {
+            self.start_game().await
+        } else {
+            tokio::try_join!(
+                self.broadcast_lobby_state(),
+                self.broadcast_system_msg(format!("{} is ready", username)),
+            )?;
+            Ok(())
+        }
+    }
+
+    /// starts a new skribbl game with the players in the room, ending whatever was going on or drawn before
+    async fn start_game(&mut self) -> Result<()> {
+        let players = self.players().cloned().collect::<Vec<Username>>();
+        let words = match &self.words {
+            Some(words) if !players.is_empty() => words.clone(),
+            _ => return Ok(()),
+        };
+        info!(player_cnt = players.len(), "starting a game");
+        let skribbl_state = SkribblState::new(players, words, self.settings.clone());
+        self.game_state = GameState::Skribbl(Box::new(skribbl_state));
+        self.paused_by_host = false;
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast_skribbl_state(),
+            self.broadcast_scoreboard(),
+            self.send_word_choices(),
+            self.broadcast_system_msg("The game starts!".to_string()),
+        )?;
+        Ok(())
+    }
+
+    /// reads the word lists again. If that fails, the room keeps the words it has
+    async fn reload_words(&mut self, host: &Username) -> Result<()> {
+        let msg = if self.word_sources.is_empty() {
+            "The server has no word list".to_string()
+        } else {
+            match read_word_lists(&self.word_sources).await {
+                Ok(words) if words.is_empty() => {
+                    "The word lists are empty, keeping the old words".to_string()
+                }
+                Ok(words) => {
+                    info!(host = %host, word_cnt = words.len(), "reloaded the words");
+                    let msg = format!(
+                        "Loaded {} words, they are used from the next game on",
+                        words.len()
+                    );
+                    self.words = Some(words);
+                    msg
+                }
+                Err(err) => {
+                    warn!(host = %host, "could not reload the words: {}", err);
+                    format!("Keeping the old words, {}", err)
+                }
+            }
+        };
+        self.send_to(host, new_message(Message::SystemMsg(msg)))
+            .await
+    }
+
+    /// adds a word to the room's word list, and to the words the running game may still offer.
+    /// Only the host is told about it, so the word stays a secret
+    async fn add_word(&mut self, host: &Username, line: &str) -> Result<()> {
+        let word = Word::parse(line);
+        let msg = match &mut self.words {
+            None => "The server has no word list".to_string(),
+            Some(_) if word.text.is_empty() => "The word must not be empty".to_string(),
+            Some(words)
+                if words
+                    .iter()
+                    .any(|x| x.text.to_lowercase() == word.text.to_lowercase()) =>
+            {
+                format!("{} is already in the word list", word.text)
+            }
+            Some(words) => {
+                let msg = format!(
+                    "Added {}, there are {} words now",
+                    word.text,
+                    words.len() + 1
+                );
+                if let GameState::Skribbl(state) = &mut self.game_state {
+                    state.add_word(word.text.clone());
+                }
+                words.push(word);
+                msg
+            }
+        };
+        self.send_to(host, new_message(Message::SystemMsg(msg)))
+            .await
+    }
+
+    /// takes a word out of the room's word list, and out of the words the running game may still offer
+    async fn remove_word(&mut self, host: &Username, word: &str) -> Result<()> {
+        let word = word.trim().to_lowercase();
+        let msg = match &mut self.words {
+            None => "The server has no word list".to_string(),
+            Some(words) if !words.iter().any(|x| x.text.to_lowercase() == word) => {
+                format!("{} is not in the word list", word)
+            }
+            Some(words) if words.len() == 1 => "The word list can't be empty".to_string(),
+            Some(words) => {
+                words.retain(|x| x.text.to_lowercase() != word);
+                if let GameState::Skribbl(state) = &mut self.game_state {
+                    state.remove_word(&word);
+                }
+                format!("Removed {}, there are {} words now", word, words.len())
+            }
+        };
+        self.send_to(host, new_message(Message::SystemMsg(msg)))
+            .await
+    }
+
+    /// ends the game without a winner, or leaves the lobby, and lets everyone draw
+    async fn stop_game(&mut self, host: &Username) -> Result<()> {
+        if matches!(self.game_state, GameState::FreeDraw) {
+            let msg = Message::SystemMsg("There is no game going on".to_string());
+            return self.send_to(host, new_message(msg)).await;
         }
+        info!(host = %host, "host stopped the game");
+        self.game_state = GameState::FreeDraw;
+        self.paused_by_host = false;
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast(ToClientMsg::GameStopped),
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast_system_msg("The host stopped the game, everyone may draw".to_string()),
+        )?;
         Ok(())
     }
 
+    /// sends everyone the final scores and goes back to the lobby
+    async fn end_game(&mut self) -> Result<()> {
+        let scores = match self.game_state.skribbl_state() {
+            Some(state) => {
+                self.stats.lock().unwrap().record_game(state);
+                state.scoreboard()
+            }
+            None => return Ok(()),
+        };
+        info!(winner = ?scores.first().map(|(username, _)| username.to_string()), "game over");
+        self.game_state = GameState::Lobby(LobbyState::new(self.settings.min_ready_players));
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast(ToClientMsg::GameOver { scores }),
+            self.broadcast(ToClientMsg::ClearCanvas),
+            self.broadcast_lobby_state(),
+        )?;
+        self.greet_host().await
+    }
+
+    /// tells the host that they are the one who can start the game
+    async fn greet_host(&self) -> Result<()> {
+        match &self.host {
+            Some(host) if self.game_state.lobby_state().is_some() => {
+                let msg = Message::SystemMsg(
+                    "You are the host. Type !start to start the game, or !ready to get ready"
+                        .to_string(),
+                );
+                self.send_to(host, new_message(msg)).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// tells the user if the text is too long to be sent
+    async fn reject_long_text(&self, username: &Username, text: &str) -> Result<bool> {
+        if text.chars().count() <= self.max_message_length {
+            return Ok(false);
+        }
+        let msg = Message::SystemMsg(format!(
+            "Your message is too long, it may have at most {} characters",
+            self.max_message_length
+        ));
+        self.send_to(username, new_message(msg)).await?;
+        Ok(true)
+    }
+
     async fn on_new_message(&mut self, username: Username, msg: data::Message) -> Result<()> {
+        let received_at = get_time_now_millis();
+        let text = match msg {
+            // the name in the message is the client's word, the session's is the one that counts
+            Message::UserMsg(_, text) => text,
+            // only the server announces things
+            Message::SystemMsg(_) => return Ok(()),
+            // whispers have to go through on_whisper, which checks who they are for and what they say
+            Message::Whisper { .. } => return Ok(()),
+        };
+        if self.reject_long_text(&username, &text).await? {
+            return Ok(());
+        }
         let mut should_broadcast = true;
         match self.game_state {
             GameState::Skribbl(ref mut state) => {
                 let can_guess = state.can_guess(&username);
                 let remaining_time = state.remaining_time();
                 let current_word = state.current_word().to_string();
+                let normalized_word = normalize_guess(&current_word);
+                let normalized_text = normalize_guess(&text);
+                let is_choosing_word = state.is_choosing_word();
                 let noone_already_solved = state
                     .player_states
                     .iter()
                     .all(|(_, player)| !player.has_solved);
 
                 if let Some(player_state) = state.player_states.get_mut(&username) {
-                    if can_guess && msg.text().eq_ignore_ascii_case(&current_word) {
+                    if can_guess && normalized_text == normalized_word {
                         should_broadcast = false;
                         if noone_already_solved {
                             state.round_end_time -= remaining_time as u64 / 2;
                         }
-                        player_state.on_solve(remaining_time);
+                        player_state.on_solve(remaining_time, &state.settings);
+                        let guess_points = player_state.last_points;
+                        state.award_drawer(guess_points);
+                        let (solved, total) = state.guess_progress();
                         let all_solved = state.did_all_solve();
-                        if all_solved {
-                            state.next_turn();
-                        }
-                        let state = state.clone();
                         tokio::try_join!(
-                            self.broadcast(ToClientMsg::SkribblStateChanged(state)),
+                            self.broadcast_skribbl_state(),
+                            self.broadcast_scoreboard(),
+                            self.broadcast(ToClientMsg::GuessProgress { solved, total }),
                             self.broadcast_system_msg(format!("{} guessed it!", username)),
                         )?;
+                        // nobody is left to guess, so there is no point in waiting for the time to run out
                         if all_solved {
-                            self.lines.clear();
-                            tokio::try_join!(
-                                self.broadcast(ToClientMsg::ClearCanvas),
-                                self.broadcast_system_msg(format!(
-                                    "The word was: \"{}\"",
-                                    current_word
-                                ))
-                            )?;
+                            self.reveal_and_advance(None).await?;
                         }
-                    } else if is_very_close_to(msg.text().to_string(), current_word.to_string()) {
+                    } else if !is_choosing_word
+                        && state.is_drawing(&username)
+                        && normalized_text.contains(&normalized_word)
+                    {
                         should_broadcast = false;
-                        if can_guess {
-                            self.send_to(
-                                &username,
-                                ToClientMsg::NewMessage(Message::SystemMsg(
-                                    "You're very close!".to_string(),
-                                )),
-                            )
+                        self.send_to(
+                            &username,
+                            new_message(Message::SystemMsg(
+                                "You can't tell the others the word!".to_string(),
+                            )),
+                        )
+                        .await?;
+                    } else if !is_choosing_word
+                        && is_very_close_to(&normalized_text, &normalized_word)
+                    {
+                        // a guess that is almost right is still shown to everyone,
+                        // but anyone else saying something this close would give the word away
+                        let notice = if can_guess {
+                            format!("'{}' is close!", text)
+                        } else {
+                            should_broadcast = false;
+                            "That's too close to the word, so nobody else saw it".to_string()
+                        };
+                        self.send_to(&username, new_message(Message::SystemMsg(notice)))
                             .await?;
-                        }
                     }
                 }
             }
-            GameState::FreeDraw => {
-                if let Some(words) = &self.words {
-                    let skribbl_state = SkribblState::new(
-                        self.sessions.keys().cloned().collect::<Vec<Username>>(),
-                        words.clone(),
-                    );
-                    self.game_state = GameState::Skribbl(skribbl_state.clone());
-                    self.broadcast(ToClientMsg::SkribblStateChanged(skribbl_state))
-                        .await?;
-                }
-            }
+            GameState::FreeDraw | GameState::Lobby(_) => {}
         }
 
-        if should_broadcast {
-            self.broadcast(ToClientMsg::NewMessage(msg)).await?;
+        // guesses are checked above, so muted players can still guess the word
+        if should_broadcast && self.allow_chat_msg(&username).await? {
+            let msg = Message::UserMsg(username, censor(&text, &self.blocklist));
+            self.broadcast_chat_msg(msg, received_at).await?;
         }
 
         Ok(())
     }
 
-    async fn on_to_srv_msg(&mut self, username: Username, msg: ToServerMsg) -> Result<()> {
-        match msg {
-            ToServerMsg::CommandMsg(msg) => {
-                self.on_command_msg(&username, &msg).await?;
-            }
-            ToServerMsg::NewMessage(message) => {
-                self.on_new_message(username, message).await?;
-            }
-            ToServerMsg::NewLine(line) => {
-                self.lines.push(line);
-                self.broadcast(ToClientMsg::NewLine(line)).await?;
-            }
-            ToServerMsg::ClearCanvas => {
-                self.lines.clear();
-                self.broadcast(ToClientMsg::ClearCanvas).await?;
+    /// sends a private message to the receiver, and a copy of it back to the sender
+    async fn on_whisper(&mut self, from: Username, to: Username, text: String) -> Result<()> {
+        if self.reject_long_text(&from, &text).await? {
+            return Ok(());
+        }
+        if !self.sessions.contains_key(&to) {
+            let msg = Message::SystemMsg(format!("There is no player called {}", to));
+            return self.send_to(&from, new_message(msg)).await;
+        }
+        if let Some(state) = self.game_state.skribbl_state() {
+            let knows_word = state.is_drawing(&from) || state.has_solved(&from);
+            // compared like guesses, so accents or extra spaces don't sneak the word through
+            let word = normalize_guess(state.current_word());
+            if knows_word && !word.is_empty() && normalize_guess(&text).contains(&word) {
+                let msg = Message::SystemMsg("You can't tell the others the word!".to_string());
+                return self.send_to(&from, new_message(msg)).await;
             }
         }
-        Ok(())
+        if !self.allow_chat_msg(&from).await? {
+            return Ok(());
+        }
+        let msg = new_message(Message::Whisper {
+            from: from.clone(),
+            to: to.clone(),
+            text: censor(&text, &self.blocklist),
+        });
+        if from != to {
+            self.send_to(&to, msg.clone()).await?;
+        }
+        self.send_to(&from, msg).await
     }
 
-    pub async fn on_tick(&mut self) -> Result<()> {
-        let state = match &mut self.game_state {
-            GameState::Skribbl(state) => state,
-            _ => return Ok(()),
-        };
+    /// in a skribbl game only the drawing user may touch the canvas, in free draw everyone may
+    fn may_draw(&self, username: &Username) -> bool {
+        match &self.game_state {
+            GameState::Skribbl(state) => state.is_drawing(username),
+            GameState::FreeDraw | GameState::Lobby(_) => true,
+        }
+    }
 
-        let remaining_time = state.remaining_time();
-        let revealed_char_cnt = state.revealed_characters().len();
+    /// takes one of the chat messages the user may send. Users who send too many are muted for a while,
+    /// and users the host muted may not send any
+    async fn allow_chat_msg(&mut self, username: &Username) -> Result<bool> {
+        if self.muted_players.contains(username) {
+            let msg = Message::SystemMsg("You were muted by the host".to_string());
+            self.send_to(username, new_message(msg)).await?;
+            return Ok(false);
+        }
+        let session = match self.sessions.get_mut(username) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        let now = Instant::now();
+        if session.muted_until.is_some_and(|until| now < until) {
+            return Ok(false);
+        }
+        session.muted_until = None;
+        if session.chat_limiter.try_acquire() {
+            return Ok(true);
+        }
+        session.muted_until = Some(now + CHAT_MUTE_DURATION);
+        let msg = Message::SystemMsg(format!(
+            "You are sending messages too fast, you are muted for {} seconds",
+            CHAT_MUTE_DURATION.as_secs()
+        ));
+        self.send_to(username, new_message(msg)).await?;
+        Ok(false)
+    }
 
-        if remaining_time <= 0 {
-            let old_word = state.current_word().to_string();
-            if let Some(ref mut drawing_user) = state.player_states.get_mut(&state.drawing_user) {
-                drawing_user.score += 50;
+    /// takes one of the lines the user may draw per second. If they drew too many, they are told once
+    async fn allow_line(&mut self, username: &Username) -> Result<bool> {
+        let session = match self.sessions.get_mut(username) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        if session.line_limiter.try_acquire() {
+            session.warned_about_line_rate = false;
+            return Ok(true);
+        }
+        let should_warn = !session.warned_about_line_rate;
+        session.warned_about_line_rate = true;
+        if should_warn {
+            let msg = Message::SystemMsg("You are drawing too fast, slow down!".to_string());
+            self.send_to(username, new_message(msg)).await?;
+        }
+        Ok(false)
+    }
+
+    /// stores a line, fill or shape, unless the canvas is already full. Returns whether it was stored.
+    fn push_canvas_op(&mut self, op: CanvasOp) -> bool {
+        if self.canvas_ops.len() >= self.max_lines {
+            return false;
+        }
+        self.canvas_ops.push(op);
+        self.recording.record(CanvasEdit::Op(op));
+        true
+    }
+
+    /// the user already drew the op on their own canvas, so it has to be taken back there.
+    /// Other users' ops may have arrived after it, so the client is told exactly which one
+    async fn reject_canvas_op(&self, username: &Username, op: CanvasOp) -> Result<()> {
+        self.send_to(username, ToClientMsg::CanvasOpRejected(op))
+            .await
+    }
+
+    /// sizes the canvas so that every player, and the one joining, can show all of it.
+    /// It never gets bigger than configured, nor smaller than MIN_DIMENSIONS.
+    /// Spectators have to make do with what fits on their terminal
+    async fn fit_canvas(&mut self, joining: Option<(usize, usize)>) -> Result<()> {
+        let client_dimensions = self
+            .sessions
+            .values()
+            .filter(|session| !session.spectator)
+            .filter_map(|session| session.dimensions)
+            .chain(joining);
+        let dimensions = client_dimensions.fold(self.max_dimensions, |(width, height), client| {
+            (
+                width.min(client.0.max(MIN_DIMENSIONS.0)),
+                height.min(client.1.max(MIN_DIMENSIONS.1)),
+            )
+        });
+        if dimensions == self.dimensions {
+            return Ok(());
+        }
+        info!(?dimensions, "resizing the canvas");
+        self.dimensions = dimensions;
+        self.broadcast(ToClientMsg::DimensionsChanged {
+            dimensions,
+            canvas_ops: self.visible_canvas_ops(),
+        })
+        .await
+    }
+
+    /// the canvas ops as the clients have them, moved onto the current canvas
+    fn visible_canvas_ops(&self) -> Vec<CanvasOp> {
+        self.canvas_ops
+            .iter()
+            .filter_map(|op| op.clamp_to(self.dimensions))
+            .collect()
+    }
+
+    /// clears the canvas, keeping what was drawn on it for replays
+    fn clear_canvas(&mut self) {
+        self.canvas_ops.clear();
+        let recording = std::mem::take(&mut self.recording).finish();
+        if !recording.is_empty() {
+            self.last_recording = recording;
+        }
+    }
+
+    /// plays the last drawing back to the user, at the speed it was drawn
+    async fn replay_drawing(&self, username: &Username) -> Result<()> {
+        let session = match self.sessions.get(username) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if self.last_recording.is_empty() {
+            let msg = Message::SystemMsg("There is no drawing to replay yet".to_string());
+            session.send(new_message(msg)).await?;
+            return session.send(ToClientMsg::ReplayEnded).await;
+        }
+        let frames = self.last_recording.clone();
+        let mut msg_send = session.msg_send.lock().await.clone();
+        tokio::spawn(
+            async move {
+                let started_at = tokio::time::Instant::now();
+                for frame in frames {
+                    let frame_time = started_at + Duration::from_millis(frame.at as u64);
+                    tokio::time::delay_until(frame_time).await;
+                    let msg = ToClientMsg::ReplayFrame(frame).into();
+                    if msg_send.send(msg).await.is_err() {
+                        // the client left
+                        return;
+                    }
+                }
+                let _ = msg_send.send(ToClientMsg::ReplayEnded.into()).await;
             }
+            .in_current_span(),
+        );
+        Ok(())
+    }
 
-            state.next_turn();
-            let state = self.game_state.skribbl_state().unwrap().clone();
-            self.lines.clear();
-            tokio::try_join!(
-                self.broadcast(ToClientMsg::SkribblStateChanged(state)),
-                self.broadcast(ToClientMsg::ClearCanvas),
-                self.broadcast_system_msg(format!("The word was: \"{}\"", old_word)),
-            )?;
-        } else if remaining_time <= (ROUND_DURATION / 4) as u32 && revealed_char_cnt < 2
-            || remaining_time <= (ROUND_DURATION / 2) as u32 && revealed_char_cnt < 1
-        {
-            state.reveal_random_char();
-            let state = state.clone();
-            self.broadcast(ToClientMsg::SkribblStateChanged(state))
+    async fn on_to_srv_msg(&mut self, username: Username, msg: ToServerMsg) -> Result<()> {
+        match self.sessions.get_mut(&username) {
+            Some(session) if session.spectator => return Ok(()),
+            Some(session) => {
+                session.last_activity = Instant::now();
+                let is_drawing_activity = matches!(
+                    msg,
+                    ToServerMsg::NewMessage(_)
+                        | ToServerMsg::NewLine(_)
+                        | ToServerMsg::Fill(_)
+                        | ToServerMsg::Shape(_)
+                        | ToServerMsg::UndoLine
+                        | ToServerMsg::ClearCanvas
+                );
+                if is_drawing_activity {
+                    session.last_drawing_activity = Instant::now();
+                }
+            }
+            None => {}
+        }
+        match msg {
+            ToServerMsg::CommandMsg(msg) => {
+                self.on_command_msg(&username, &msg).await?;
+            }
+            ToServerMsg::NewMessage(message) => {
+                self.set_typing(&username, false).await?;
+                self.on_new_message(username, message).await?;
+            }
+            ToServerMsg::NewLine(drawn) => {
+                let mut line = drawn;
+                line.clamp_thickness();
+                line.clamp_to(self.dimensions);
+                // jittery clients send the same segment again and again, which wouldn't change the canvas
+                let is_repeated =
+                    matches!(self.canvas_ops.last(), Some(CanvasOp::Line(last)) if *last == line);
+                if is_repeated || !self.may_draw(&username) {
+                    return Ok(());
+                }
+                if self.allow_line(&username).await? && self.push_canvas_op(CanvasOp::Line(line)) {
+                    self.pending_lines.push((username, line));
+                } else {
+                    // the client knows the line as it drew it, not as it was clamped
+                    self.reject_canvas_op(&username, CanvasOp::Line(drawn))
+                        .await?;
+                }
+            }
+            ToServerMsg::Fill(fill) => {
+                let in_bounds =
+                    (fill.x as usize) < self.dimensions.0 && (fill.y as usize) < self.dimensions.1;
+                if !in_bounds || !self.may_draw(&username) {
+                    return Ok(());
+                }
+                if self.push_canvas_op(CanvasOp::Fill(fill)) {
+                    self.broadcast_except(&username, ToClientMsg::Fill(fill))
+                        .await?;
+                } else {
+                    self.reject_canvas_op(&username, CanvasOp::Fill(fill))
+                        .await?;
+                }
+            }
+            ToServerMsg::Shape(drawn) => {
+                let mut shape = drawn;
+                shape.clamp_thickness();
+                shape.clamp_to(self.dimensions);
+                if !self.may_draw(&username) {
+                    return Ok(());
+                }
+                if self.allow_line(&username).await? && self.push_canvas_op(CanvasOp::Shape(shape))
+                {
+                    self.broadcast_except(&username, ToClientMsg::Shape(shape))
+                        .await?;
+                } else {
+                    self.reject_canvas_op(&username, CanvasOp::Shape(drawn))
+                        .await?;
+                }
+            }
+            ToServerMsg::UndoLine => {
+                if !self.may_draw(&username) {
+                    return Ok(());
+                }
+                if let Some(op) = self.canvas_ops.pop() {
+                    self.recording.record(CanvasEdit::Undo);
+                    // a fill that isn't on the shrunk canvas was never sent to the clients
+                    if op.clamp_to(self.dimensions).is_some() {
+                        self.broadcast(ToClientMsg::UndoLine).await?;
+                    }
+                }
+            }
+            ToServerMsg::ClearCanvas => {
+                if self.may_draw(&username) {
+                    self.clear_canvas();
+                    self.broadcast(ToClientMsg::ClearCanvas).await?;
+                }
+            }
+            ToServerMsg::Ready => self.on_ready(username).await?,
+            ToServerMsg::Whisper { to, text } => self.on_whisper(username, to, text).await?,
+            ToServerMsg::Typing(typing) => {
+                // the drawing user can't guess, so nobody needs to wait for them
+                let is_drawing = self
+                    .game_state
+                    .skribbl_state()
+                    .is_some_and(|state| state.is_drawing(&username));
+                self.set_typing(&username, typing && !is_drawing).await?;
+            }
+            ToServerMsg::WordChosen(word) => {
+                if let GameState::Skribbl(ref mut state) = self.game_state {
+                    if state.is_drawing(&username) && state.choose_word(&word) {
+                        tokio::try_join!(
+                            self.broadcast_skribbl_state(),
+                            self.broadcast_round_started(),
+                            self.send_your_turn()
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// remembers whether the user is typing, and tells everyone else if that changed
+    async fn set_typing(&mut self, username: &Username, typing: bool) -> Result<()> {
+        let was_typing = if typing {
+            self.typing
+                .insert(username.clone(), Instant::now())
+                .is_some()
+        } else {
+            self.typing.remove(username).is_some()
+        };
+        if was_typing == typing {
+            return Ok(());
+        }
+        self.broadcast_except(username, ToClientMsg::UserTyping(username.clone(), typing))
+            .await
+    }
+
+    /// stops the typing of players that didn't repeat it in time, and of the drawing user
+    async fn clear_stale_typing(&mut self) -> Result<()> {
+        let drawing_user = self
+            .game_state
+            .skribbl_state()
+            .map(|state| state.drawing_user.clone());
+        let stale = self
+            .typing
+            .iter()
+            .filter(|(username, since)| {
+                since.elapsed() > TYPING_TIMEOUT || drawing_user.as_ref() == Some(username)
+            })
+            .map(|(username, _)| username.clone())
+            .collect::<Vec<_>>();
+        for username in stale {
+            self.set_typing(&username, false).await?;
+        }
+        Ok(())
+    }
+
+    /// removes the players that didn't do anything for longer than the idle timeout.
+    /// The drawing user only counts as active while they draw or chat, and may go without either
+    /// for half the turn at most.
+    async fn kick_idle_players(&mut self) -> Result<()> {
+        let state = match &self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+        let idle_timeout = state.settings.idle_timeout;
+        let drawer_idle_timeout = idle_timeout.min(state.settings.round_duration / 2);
+        // the drawing user may not have drawn lately because their turn only just started
+        let drawer_may_be_idle =
+            !state.is_choosing_word() && state.elapsed_time() >= drawer_idle_timeout;
+
+        let idle_users = self
+            .sessions
+            .values()
+            .filter(|session| !session.spectator)
+            .filter(|session| {
+                if state.is_drawing(&session.username) {
+                    drawer_may_be_idle
+                        && session.last_drawing_activity.elapsed() >= drawer_idle_timeout
+                } else {
+                    session.last_activity.elapsed() >= idle_timeout
+                }
+            })
+            .map(|session| session.username.clone())
+            .collect::<Vec<_>>();
+
+        for username in idle_users {
+            info!(player = %username, "removing idle player");
+            let reason = "You were removed for being idle".to_string();
+            self.kick_player(&username, reason).await?;
+            self.broadcast_system_msg(format!("{} was removed for being idle", username))
                 .await?;
         }
+        Ok(())
+    }
 
-        self.broadcast(ToClientMsg::TimeChanged(remaining_time as u32))
-            .await?;
+    /// sends the lines drawn since the last flush to every client, in one message per client.
+    /// Every client gets all lines except their own, which they already drew on their own canvas.
+    async fn flush_pending_lines(&mut self) -> Result<()> {
+        if self.pending_lines.is_empty() {
+            return Ok(());
+        }
+        let pending_lines = std::mem::take(&mut self.pending_lines);
+        // usually only a few clients drew, so everyone else shares the message with all the lines
+        let all_lines = Arc::new(SharedMsg::new(ToClientMsg::NewLines(
+            pending_lines.iter().map(|(_, line)| *line).collect(),
+        )));
+        futures_util::future::try_join_all(self.sessions.iter().map(|(username, session)| {
+            let drew_some = pending_lines.iter().any(|(drawer, _)| drawer == username);
+            let msg = if !drew_some {
+                Some(all_lines.clone())
+            } else {
+                let lines = pending_lines
+                    .iter()
+                    .filter(|(drawer, _)| drawer != username)
+                    .map(|(_, line)| *line)
+                    .collect::<Vec<_>>();
+                if lines.is_empty() {
+                    None
+                } else {
+                    Some(ToClientMsg::NewLines(lines).into())
+                }
+            };
+            async move {
+                if let Some(msg) = msg {
+                    session.send(msg).await?;
+                }
+                Ok::<_, ServerError>(())
+            }
+        }))
+        .await?;
+        Ok(())
+    }
+
+    async fn on_event(&mut self, evt: ServerEvent) -> Result<()> {
+        // anything else might change the canvas or send it to someone,
+        // so the lines drawn before have to go out first
+        if !matches!(evt, ServerEvent::ToServerMsg(_, ToServerMsg::NewLine(_))) {
+            self.flush_pending_lines().await?;
+        }
+        match evt {
+            ServerEvent::ToServerMsg(name, msg) => self.on_to_srv_msg(name, msg).await,
+            ServerEvent::UserJoined(attempt) => self.on_user_joined(*attempt).await,
+            ServerEvent::UserLeft(username, membership) => {
+                self.on_user_left(&username, membership).await
+            }
+            // handled in run, as it stops the room
+            ServerEvent::Shutdown(_) => Ok(()),
+            ServerEvent::CanvasRequested(canvas_send) => {
+                let _ = canvas_send.send((self.dimensions, self.visible_canvas_ops()));
+                Ok(())
+            }
+        }
+    }
 
+    /// tells everyone the server is shutting down and closes their connections
+    async fn shutdown(&mut self) -> Result<()> {
+        self.flush_pending_lines().await?;
+        self.broadcast(ToClientMsg::ServerShutdown).await?;
+        // dropping a session closes its connection once the messages sent before went out
+        self.sessions.clear();
         Ok(())
     }
 
-    pub async fn on_user_joined(&mut self, session: UserSession) -> Result<()> {
-        if let GameState::Skribbl(ref mut state) = self.game_state {
+    fn snapshot(&self) -> RoomSnapshot {
+        RoomSnapshot {
+            canvas_ops: self.canvas_ops.clone(),
+            dimensions: self.dimensions,
+            game_state: self.game_state.clone(),
+            chat_history: self.chat_history.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+
+    /// continues where the room was before the server restarted. Nobody is connected yet,
+    /// so a running game is paused, and a lobby starts over
+    fn restore(&mut self, snapshot: RoomSnapshot) {
+        self.canvas_ops = snapshot.canvas_ops;
+        self.dimensions = snapshot.dimensions;
+        *self.chat_history.lock().unwrap() = snapshot.chat_history.into();
+        if let GameState::Skribbl(mut state) = snapshot.game_state {
+            state.pause();
+            self.game_state = GameState::Skribbl(state);
+        }
+    }
+
+    /// ends the current turn, tells everyone what the word was and clears the canvas.
+    /// Every way a turn ends goes through here: everyone guessed the word, the time ran out,
+    /// the turn was skipped, or the drawing user left, who is then removed from the game
+    async fn reveal_and_advance(&mut self, leaving_drawer: Option<&Username>) -> Result<()> {
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+        let old_word = state.current_word().to_string();
+        self.stats.lock().unwrap().record_turn(state);
+        match leaving_drawer {
+            Some(username) => state.remove_user(username),
+            None => {
+                state.next_turn();
+            }
+        }
+        debug!(round = state.round, drawing_user = %state.drawing_user, "next turn");
+        let game_over = state.is_game_over();
+        self.clear_canvas();
+        tokio::try_join!(
+            self.broadcast_skribbl_state(),
+            self.send_word_choices(),
+            self.broadcast(ToClientMsg::ClearCanvas),
+        )?;
+        // there is no word yet while the drawing user is still choosing one, or between turns
+        if !old_word.is_empty() {
+            self.broadcast_system_msg(format!("The word was: \"{}\"", old_word))
+                .await?;
+        }
+        if game_over {
+            self.end_game().await
+        } else {
+            self.broadcast_next_drawer().await
+        }
+    }
+
+    pub async fn on_tick(&mut self) -> Result<()> {
+        self.flush_pending_lines().await?;
+        self.remove_disconnected_players().await?;
+        self.kick_idle_players().await?;
+        self.clear_stale_typing().await?;
+
+        let state = match &mut self.game_state {
+            GameState::Skribbl(state) => state,
+            _ => return Ok(()),
+        };
+
+        let remaining_time = state.remaining_time();
+        let round_end = state.round_end_millis();
+
+        if state.is_between_turns() {
+            if state.is_time_up() {
+                state.start_turn();
+                tokio::try_join!(self.broadcast_skribbl_state(), self.send_word_choices())?;
+            }
+        } else if state.is_choosing_word() {
+            if state.is_time_up() && state.choose_first_word() {
+                tokio::try_join!(
+                    self.broadcast_skribbl_state(),
+                    self.broadcast_round_started(),
+                    self.send_your_turn()
+                )?;
+            }
+        } else if state.is_time_up() {
+            self.reveal_and_advance(None).await?;
+            if self.game_state.skribbl_state().is_none() {
+                // that was the last turn of the game
+                return Ok(());
+            }
+        } else if state.revealed_characters().len() < state.due_hint_count()
+            && state.reveal_random_char()
+        {
+            self.broadcast_word_hint().await?;
+        }
+
+        // with short tick intervals, many ticks fall into the same second
+        if self.last_time_changed == Some((remaining_time, round_end)) {
+            return Ok(());
+        }
+        self.last_time_changed = Some((remaining_time, round_end));
+        self.broadcast(ToClientMsg::TimeChanged {
+            remaining_secs: remaining_time,
+            server_time: get_time_now_millis(),
+            round_end,
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn on_user_joined(&mut self, attempt: JoinAttempt) -> Result<()> {
+        let JoinAttempt {
+            mut session,
+            request,
+            join_send,
+        } = attempt;
+        session.dimensions = request.dimensions;
+
+        if let Some(password) = &self.password {
+            let password_matches = request
+                .password
+                .is_some_and(|given| constant_time_eq(&given, password));
+            if !password_matches {
+                let rejection = JoinRejection::Close("bad password".to_string());
+                let _ = join_send.send(Err(rejection));
+                return Ok(());
+            }
+        }
+
+        if self.sessions.contains_key(&session.username) {
+            let reason = format!("The name {} is already taken", session.username);
+            let _ = join_send.send(Err(JoinRejection::Retry(reason)));
+            return Ok(());
+        }
+
+        // a player whose connection dropped gets their place back by joining with the same name
+        // and their reconnect token. Until then, nobody else may take the name
+        if let Some(player) = self.disconnected_players.get(&session.username) {
+            let token_matches = request
+                .reconnect_token
+                .as_ref()
+                .is_some_and(|given| constant_time_eq(given, &player.reconnect_token));
+            if !token_matches || session.spectator {
+                let reason = format!("The name {} is already taken", session.username);
+                let _ = join_send.send(Err(JoinRejection::Retry(reason)));
+                return Ok(());
+            }
+        }
+        let spectator = session.spectator;
+        let reconnecting = self.disconnected_players.contains_key(&session.username);
+        if let (Some(max_players), false) = (self.max_players, spectator || reconnecting) {
+            // players that may still reconnect keep their place
+            if self.players().count() + self.disconnected_players.len() >= max_players {
+                let rejection = JoinRejection::Close("the room is full".to_string());
+                let _ = join_send.send(Err(rejection));
+                return Ok(());
+            }
+        }
+
+        let join_msg = if reconnecting {
+            info!(player = %session.username, "player reconnected");
+            self.disconnected_players.remove(&session.username);
+            format!("{} reconnected", session.username)
+        } else {
+            format!("{} joined", session.username)
+        };
+        if let (GameState::Skribbl(ref mut state), false) = (&mut self.game_state, spectator) {
+            // a reconnecting player is usually still in the game, with their score
             state.add_player(session.username.clone());
-            let state = state.clone();
             tokio::try_join!(
-                self.broadcast(ToClientMsg::SkribblStateChanged(state)),
-                self.broadcast_system_msg(format!("{} joined", session.username)),
+                self.broadcast_skribbl_state(),
+                self.broadcast_system_msg(join_msg),
             )?;
+        } else if reconnecting {
+            self.broadcast_system_msg(join_msg).await?;
         }
 
+        self.fit_canvas(session.dimensions.filter(|_| !spectator))
+            .await?;
+        let color = self.assign_color(&session.username);
         let initial_state = InitialState {
-            lines: self.lines.clone(),
-            skribbl_state: self.game_state.skribbl_state().cloned(),
+            canvas_ops: self.visible_canvas_ops(),
+            skribbl_state: self
+                .game_state
+                .skribbl_state()
+                .map(|state| state.for_user(&session.username)),
             dimensions: self.dimensions,
+            lobby_state: self.game_state.lobby_state().cloned(),
+            palette: data::PALETTE.to_vec(),
+            chat_history: self.chat_history.lock().unwrap().iter().cloned().collect(),
+            color,
         };
         session
             .send(ToClientMsg::InitialState(initial_state))
             .await?;
-        self.sessions.insert(session.username.clone(), session);
+        if !spectator {
+            session
+                .send(ToClientMsg::ReconnectToken(session.reconnect_token.clone()))
+                .await?;
+        }
+        let username = session.username.clone();
+        self.sessions.insert(username.clone(), session);
+        let _ = join_send.send(Ok(()));
+        tokio::try_join!(
+            self.broadcast_spectator_count(),
+            self.broadcast_player_list()
+        )?;
+        if spectator {
+            return Ok(());
+        }
+        self.broadcast_scoreboard().await?;
+        let player_cnt = self.players().count();
+        let mut resumed = false;
+        if let GameState::Skribbl(ref mut state) = self.game_state {
+            if state.is_paused() && !self.paused_by_host && player_cnt >= state.settings.min_players
+            {
+                info!(player_cnt, "continuing the game");
+                remove_absent_players(state, &self.sessions, &self.disconnected_players);
+                if state.is_game_over() {
+                    self.end_game().await?;
+                } else {
+                    state.resume();
+                    resumed = true;
+                    tokio::try_join!(
+                        self.broadcast_skribbl_state(),
+                        self.broadcast_scoreboard(),
+                        self.broadcast_system_msg("The game continues!".to_string()),
+                        self.send_word_choices(),
+                        self.send_your_turn(),
+                    )?;
+                }
+            }
+        }
+        let is_drawing = self
+            .game_state
+            .skribbl_state()
+            .is_some_and(|state| state.is_drawing(&username));
+        if reconnecting && is_drawing && !resumed {
+            // the drawing user lost the connection during their turn
+            tokio::try_join!(self.send_word_choices(), self.send_your_turn())?;
+        }
+        let has_host_key = match (&self.host_key, &request.host_key) {
+            (Some(host_key), Some(given)) => constant_time_eq(given, host_key),
+            _ => false,
+        };
+        let is_host = self.host.as_ref() == Some(&username);
+        if self.host.is_none() || has_host_key || is_host {
+            self.host = Some(username);
+            self.greet_host().await?;
+        }
+        Ok(())
+    }
+
+    async fn broadcast_spectator_count(&self) -> Result<()> {
+        let spectator_cnt = self
+            .sessions
+            .values()
+            .filter(|session| session.spectator)
+            .count();
+        self.broadcast(ToClientMsg::SpectatorCount(spectator_cnt))
+            .await
+    }
+
+    /// picks a color for the user, unless they still have one from before they lost the connection.
+    /// A name always gets the same color, unless someone else in the room already has it
+    fn assign_color(&mut self, username: &Username) -> CanvasColor {
+        if let Some(color) = self.user_colors.get(username) {
+            return *color;
+        }
+        let hash = username.to_string().bytes().fold(0usize, |hash, byte| {
+            hash.wrapping_mul(31).wrapping_add(byte as usize)
+        });
+        let preferred = hash % data::USER_COLORS.len();
+        let color = (0..data::USER_COLORS.len())
+            .map(|offset| data::USER_COLORS[(preferred + offset) % data::USER_COLORS.len()])
+            .find(|color| !self.user_colors.values().any(|taken| taken == color))
+            .unwrap_or(data::USER_COLORS[preferred]);
+        self.user_colors.insert(username.clone(), color);
+        color
+    }
+
+    async fn broadcast_player_list(&self) -> Result<()> {
+        let color_of =
+            |username: &Username| self.user_colors.get(username).copied().unwrap_or_default();
+        let connected = self.sessions.values().map(|session| PlayerListEntry {
+            username: session.username.clone(),
+            spectator: session.spectator,
+            connected: true,
+            color: color_of(&session.username),
+        });
+        let disconnected = self
+            .disconnected_players
+            .keys()
+            .map(|username| PlayerListEntry {
+                username: username.clone(),
+                spectator: false,
+                connected: false,
+                color: color_of(username),
+            });
+        let mut players = connected.chain(disconnected).collect::<Vec<_>>();
+        players.sort_by(|a, b| a.username.cmp(&b.username));
+        self.broadcast(ToClientMsg::PlayerList(players)).await
+    }
+
+    async fn broadcast_lobby_state(&self) -> Result<()> {
+        if let Some(lobby) = self.game_state.lobby_state() {
+            self.broadcast(ToClientMsg::LobbyStateChanged(lobby.clone()))
+                .await?;
+        }
         Ok(())
     }
 
     /// send a Message::SystemMsg to all active sessions
     async fn broadcast_system_msg(&self, msg: String) -> Result<()> {
-        self.broadcast(ToClientMsg::NewMessage(Message::SystemMsg(msg)))
+        self.broadcast_chat_msg(Message::SystemMsg(msg), get_time_now_millis())
+            .await
+    }
+
+    /// send a chat message the server got at the given time to everyone,
+    /// and remember it for players joining later
+    async fn broadcast_chat_msg(&self, message: Message, sent_at: u64) -> Result<()> {
+        let msg = TimedMessage { message, sent_at };
+        {
+            let mut chat_history = self.chat_history.lock().unwrap();
+            if chat_history.len() == CHAT_HISTORY_LENGTH {
+                chat_history.pop_front();
+            }
+            chat_history.push_back(msg.clone());
+        }
+        self.broadcast(ToClientMsg::NewMessage(msg)).await
+    }
+
+    /// let the drawing user know which words they can choose from, if they're still choosing
+    async fn send_word_choices(&self) -> Result<()> {
+        if let Some(state) = self.game_state.skribbl_state() {
+            // in a restored game, the drawing user may not have come back yet
+            let drawer_session = self.sessions.get(&state.drawing_user);
+            let is_choosing_word = state.is_choosing_word() && !state.is_between_turns();
+            if let (true, Some(session)) = (is_choosing_word, drawer_session) {
+                let choices = state.word_choices().to_vec();
+                session.send(ToClientMsg::ChooseWord(choices)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// tells everyone who draws after the break between turns
+    async fn broadcast_next_drawer(&self) -> Result<()> {
+        if let Some(state) = self.game_state.skribbl_state() {
+            let drawer = state.drawing_user.clone();
+            self.broadcast(ToClientMsg::NextDrawer(drawer)).await?;
+        }
+        Ok(())
+    }
+
+    /// tells everyone who draws in which round, and how long the word is, once it was chosen
+    async fn broadcast_round_started(&self) -> Result<()> {
+        if let Some(state) = self.game_state.skribbl_state() {
+            self.broadcast(ToClientMsg::RoundStarted {
+                round: state.round,
+                drawer: state.drawing_user.clone(),
+                word: state.hinted_current_word(),
+            })
             .await?;
+        }
+        Ok(())
+    }
+
+    /// tells the drawing user which word to draw, once they chose it
+    async fn send_your_turn(&self) -> Result<()> {
+        if let Some(state) = self.game_state.skribbl_state() {
+            let drawer_session = self.sessions.get(&state.drawing_user);
+            if let (false, Some(session)) = (state.is_choosing_word(), drawer_session) {
+                let word = state.current_word().to_string();
+                session.send(ToClientMsg::YourTurn { word }).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// send the hinted current word to everyone except the drawing user
+    async fn broadcast_word_hint(&self) -> Result<()> {
+        let state = match self.game_state.skribbl_state() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let msg = Arc::new(SharedMsg::new(ToClientMsg::WordHint(
+            state.hinted_current_word(),
+        )));
+        futures_util::future::try_join_all(
+            self.sessions
+                .iter()
+                .filter(|(username, _)| !state.is_drawing(username))
+                .map(|(_, session)| session.send(msg.clone())),
+        )
+        .await?;
         Ok(())
     }
 
@@ -312,8 +2000,42 @@ impl ServerState {
         Ok(())
     }
 
+    /// send the current SkribblState to all running sessions,
+    /// hiding the current word from everyone except the drawing user
+    async fn broadcast_skribbl_state(&self) -> Result<()> {
+        let state = match self.game_state.skribbl_state() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        // there are only two versions of the state, the drawing user's and everyone else's
+        let drawer_msg = Arc::new(SharedMsg::new(ToClientMsg::SkribblStateChanged(
+            state.for_user(&state.drawing_user),
+        )));
+        let guesser_msg = Arc::new(SharedMsg::new(ToClientMsg::SkribblStateChanged(
+            state.for_guessers(),
+        )));
+        futures_util::future::try_join_all(self.sessions.iter().map(|(username, session)| {
+            if state.is_drawing(username) {
+                session.send(drawer_msg.clone())
+            } else {
+                session.send(guesser_msg.clone())
+            }
+        }))
+        .await?;
+        Ok(())
+    }
+
+    async fn broadcast_scoreboard(&self) -> Result<()> {
+        if let Some(state) = self.game_state.skribbl_state() {
+            self.broadcast(ToClientMsg::Scoreboard(state.scoreboard()))
+                .await?;
+        }
+        Ok(())
+    }
+
     /// broadcast a ToClientMsg to all running sessions
     async fn broadcast(&self, msg: ToClientMsg) -> Result<()> {
+        let msg = Arc::new(SharedMsg::new(msg));
         futures_util::future::try_join_all(
             self.sessions
                 .iter()
@@ -323,155 +2045,824 @@ impl ServerState {
         Ok(())
     }
 
-    /// run the main server, reacting to any server events
+    /// send a ToClientMsg to every session but the given user's
+    async fn broadcast_except(&self, except: &Username, msg: ToClientMsg) -> Result<()> {
+        let msg = Arc::new(SharedMsg::new(msg));
+        futures_util::future::try_join_all(
+            self.sessions
+                .iter()
+                .filter(|(username, _)| *username != except)
+                .map(|(_, session)| session.send(msg.clone())),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// run the main server, reacting to any server events and ticking the game clock.
+    /// Stops once every sender of server events has been dropped.
     async fn run(&mut self, mut evt_recv: tokio::sync::mpsc::Receiver<ServerEvent>) -> Result<()> {
-        loop {
-            if let Some(evt) = evt_recv.recv().await {
-                match evt {
-                    ServerEvent::ToServerMsg(name, msg) => {
-                        self.on_to_srv_msg(name.into(), msg).await?
+        let mut tick_interval = tokio::time::interval(self.tick_interval);
+        let mut line_batch_interval = tokio::time::interval(LINE_BATCH_INTERVAL);
+        let result = loop {
+            let result = tokio::select! {
+                _ = tick_interval.tick() => self.on_tick().await,
+                _ = line_batch_interval.tick() => self.flush_pending_lines().await,
+                maybe_evt = evt_recv.recv() => match maybe_evt {
+                    Some(ServerEvent::Shutdown(snapshot_send)) => {
+                        let result = self.shutdown().await;
+                        let _ = snapshot_send.send(self.snapshot());
+                        break result;
                     }
-                    ServerEvent::UserJoined(session) => self.on_user_joined(session).await?,
-                    ServerEvent::UserLeft(username) => self.remove_player(&username).await?,
-                    ServerEvent::Tick => self.on_tick().await?,
+                    Some(evt) => self.on_event(evt).await,
+                    None => break Ok(()),
                 }
+            };
+            // one failing event shouldn't take the whole room down
+            if let Err(err) = result.and(self.remove_slow_clients().await) {
+                error!("{}", err);
             }
+            self.report_metrics();
+        };
+        // the room is gone, so it doesn't count anymore
+        self.reported_metrics
+            .report(&self.metrics, RoomMetrics::default());
+        result
+    }
+
+    /// removes the clients that stopped taking messages, so they don't hold up the room any longer
+    async fn remove_slow_clients(&mut self) -> Result<()> {
+        let slow_users = self
+            .sessions
+            .values()
+            .filter(|session| session.is_too_slow())
+            .map(|session| session.username.clone())
+            .collect::<Vec<_>>();
+        for username in slow_users {
+            self.remove_player(&username).await?;
+            self.broadcast_system_msg(format!("{} was removed for lagging behind", username))
+                .await?;
         }
+        Ok(())
+    }
+
+    fn report_metrics(&mut self) {
+        let current = RoomMetrics {
+            sessions: self.sessions.len() as i64,
+            active_games: self.game_state.skribbl_state().is_some() as i64,
+            canvas_ops: self.canvas_ops.len() as i64,
+        };
+        self.reported_metrics.report(&self.metrics, current);
     }
 }
 
-pub async fn run_server(
-    addr: &str,
-    dimensions: (usize, usize),
-    word_file: Option<PathBuf>,
-) -> Result<()> {
+#[derive(Debug)]
+struct RoomHandle {
+    evt_send: tokio::sync::mpsc::Sender<ServerEvent>,
+    member_cnt: usize,
+}
+
+/// all the rooms that currently have players in them, together with what new rooms are started with
+#[derive(Debug)]
+struct Rooms {
+    rooms: Mutex<HashMap<RoomId, RoomHandle>>,
+    config: ServerConfig,
+    words: Option<Vec<Word>>,
+    blocklist: Vec<String>,
+    stats: Arc<std::sync::Mutex<StatsStore>>,
+    bans: Arc<std::sync::Mutex<BanList>>,
+    /// rooms from before the server restarted that nobody joined yet
+    saved_rooms: std::sync::Mutex<HashMap<RoomId, RoomSnapshot>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Rooms {
+    fn new(
+        config: ServerConfig,
+        words: Option<Vec<Word>>,
+        blocklist: Vec<String>,
+        stats: Arc<std::sync::Mutex<StatsStore>>,
+        bans: Arc<std::sync::Mutex<BanList>>,
+        saved_rooms: HashMap<RoomId, RoomSnapshot>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Rooms {
+            rooms: Mutex::new(HashMap::new()),
+            config,
+            words,
+            blocklist,
+            stats,
+            bans,
+            saved_rooms: std::sync::Mutex::new(saved_rooms),
+            metrics,
+        }
+    }
+
+    /// returns the event sender of the given room, starting the room if it doesn't exist yet.
+    /// The caller counts as a member of the room until it calls `leave`.
+    async fn join(&self, room: &RoomId) -> tokio::sync::mpsc::Sender<ServerEvent> {
+        let mut rooms = self.rooms.lock().await;
+        let handle = rooms.entry(room.clone()).or_insert_with(|| {
+            info!(%room, "starting room");
+            self.metrics.rooms.inc();
+            RoomHandle {
+                evt_send: self.start_room(room),
+                member_cnt: 0,
+            }
+        });
+        handle.member_cnt += 1;
+        handle.evt_send.clone()
+    }
+
+    /// stops counting the caller as a member of the room. Once a room has no members left,
+    /// it is removed, and its server state stops as soon as the last event sender is dropped.
+    async fn leave(&self, room: &RoomId) {
+        let mut rooms = self.rooms.lock().await;
+        if let Some(handle) = rooms.get_mut(room) {
+            handle.member_cnt -= 1;
+            if handle.member_cnt == 0 {
+                info!(%room, "closing room");
+                self.metrics.rooms.dec();
+                rooms.remove(room);
+            }
+        }
+    }
+
+    /// shuts down every room, returning once all of them told their players.
+    /// Returns the snapshots of all rooms, including saved ones nobody joined
+    async fn shutdown(&self) -> HashMap<RoomId, RoomSnapshot> {
+        let rooms = self.rooms.lock().await;
+        let snapshot_recvs = rooms.iter().map(|(room, handle)| {
+            let mut evt_send = handle.evt_send.clone();
+            async move {
+                let (snapshot_send, snapshot_recv) = oneshot::channel();
+                evt_send
+                    .send(ServerEvent::Shutdown(snapshot_send))
+                    .await
+                    .ok()?;
+                Some((room.clone(), snapshot_recv.await.ok()?))
+            }
+        });
+        let snapshots = futures_util::future::join_all(snapshot_recvs).await;
+        let mut saved_rooms = std::mem::take(&mut *self.saved_rooms.lock().unwrap());
+        saved_rooms.extend(snapshots.into_iter().flatten());
+        saved_rooms
+    }
+
+    /// renders the canvas of the room in the `room` parameter as a PNG image, for `/canvas.png`.
+    /// Rooms from before a restart that nobody joined yet count too. If the server has a password,
+    /// the `password` parameter has to match it. It is sent in plain text, as the endpoint is plain HTTP
+    async fn canvas_png(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Option<(&'static str, Vec<u8>)> {
+        if let Some(password) = &self.config.password {
+            let given = params.get("password")?;
+            if !constant_time_eq(given, password) {
+                return None;
+            }
+        }
+        let room = params.get("room")?;
+        let running_room = self
+            .rooms
+            .lock()
+            .await
+            .get(room)
+            .map(|handle| handle.evt_send.clone());
+        let (dimensions, ops) = match running_room {
+            Some(mut evt_send) => {
+                let (canvas_send, canvas_recv) = oneshot::channel();
+                evt_send
+                    .send(ServerEvent::CanvasRequested(canvas_send))
+                    .await
+                    .ok()?;
+                canvas_recv.await.ok()?
+            }
+            None => {
+                let saved_rooms = self.saved_rooms.lock().unwrap();
+                let snapshot = saved_rooms.get(room)?;
+                (snapshot.dimensions, snapshot.canvas_ops.clone())
+            }
+        };
+        match render::to_png(&ops, dimensions) {
+            Ok(png) => Some(("image/png", png)),
+            Err(err) => {
+                error!("could not render the canvas: {}", err);
+                None
+            }
+        }
+    }
+
+    fn start_room(&self, room: &RoomId) -> tokio::sync::mpsc::Sender<ServerEvent> {
+        let (evt_send, evt_recv) = tokio::sync::mpsc::channel::<ServerEvent>(1);
+        // with words to guess, the room waits for a game of skribbl, otherwise it's just for drawing
+        let game_state = if self.words.is_some() {
+            GameState::Lobby(LobbyState::new(self.config.settings.min_ready_players))
+        } else {
+            GameState::FreeDraw
+        };
+        let mut server_state = ServerState::new(
+            game_state,
+            self.words.clone(),
+            &self.config,
+            self.blocklist.clone(),
+            self.stats.clone(),
+            self.bans.clone(),
+            self.metrics.clone(),
+        );
+        if let Some(snapshot) = self.saved_rooms.lock().unwrap().remove(room) {
+            info!(%room, "resuming room");
+            server_state.restore(snapshot);
+        }
+        tokio::spawn(
+            async move {
+                if let Err(err) = server_state.run(evt_recv).await {
+                    error!("room stopped: {}", err);
+                }
+            }
+            .instrument(tracing::info_span!(parent: None, "room", %room)),
+        );
+        evt_send
+    }
+}
+
+pub async fn run_server(config: ServerConfig) -> Result<()> {
+    let start_time = Instant::now();
+    let addr = config
+        .addr
+        .parse::<SocketAddr>()
+        .map_err(|_| ServerError::InvalidAddress(config.addr.clone()))?;
+    // on most systems, listening on the IPv6 address :: accepts IPv4 connections as well
     let mut server_listener = TcpListener::bind(addr)
         .await
-        .expect("Could not start webserver (could not bind)");
-
-    let maybe_words = word_file.map(|path| read_words_file(&path).unwrap());
+        .map_err(|err| ServerError::BindError(addr, err))?;
+    info!(%addr, "listening");
 
-    let (srv_event_send, srv_event_recv) = tokio::sync::mpsc::channel::<ServerEvent>(1);
-    let mut server_state = ServerState::new(GameState::FreeDraw, dimensions, maybe_words);
+    let maybe_words = if config.word_sources.is_empty() {
+        None
+    } else {
+        Some(read_word_lists(&config.word_sources).await?)
+    };
+    let tls_acceptor = config
+        .tls_files
+        .as_ref()
+        .map(load_tls_acceptor)
+        .transpose()?;
+    let blocklist = config
+        .blocklist_file
+        .as_ref()
+        .map(read_blocklist_file)
+        .transpose()?
+        .unwrap_or_default();
+    let stats = Arc::new(std::sync::Mutex::new(StatsStore::load(
+        config.stats_file.clone(),
+    )?));
+    let bans = Arc::new(std::sync::Mutex::new(BanList::load(
+        config.ban_file.clone(),
+    )?));
+    let resume_file = config.resume_file.clone();
+    let saved_rooms = match &resume_file {
+        Some(path) => Snapshot::load(path)?.unwrap_or_default().rooms,
+        None => HashMap::new(),
+    };
+    let metrics = Arc::new(Metrics::default());
+    // metrics, health checks and canvases may share a port
+    let mut http_pages = HashMap::<u16, Vec<(&'static str, http::Page)>>::new();
+    if let Some(port) = config.metrics_port {
+        let metrics = metrics.clone();
+        let page: http::Page = Box::new(move |_| {
+            let page = metrics.encode();
+            async move { Some(page) }.boxed()
+        });
+        http_pages.entry(port).or_default().push(("/metrics", page));
+    }
+    if let Some(port) = config.health_port {
+        let metrics = metrics.clone();
+        let page: http::Page = Box::new(move |_| {
+            let health = serde_json::json!({
+                "status": "ok",
+                "uptime_secs": start_time.elapsed().as_secs(),
+                "sessions": metrics.sessions.get(),
+            });
+            let page = ("application/json", health.to_string().into_bytes());
+            async move { Some(page) }.boxed()
+        });
+        http_pages.entry(port).or_default().push(("/health", page));
+    }
+    let canvas_port = config.canvas_port;
+    if canvas_port.is_some() && config.password.is_some() && config.tls_files.is_some() {
+        warn!("the canvas endpoint is plain HTTP, so the password sent to it isn't encrypted");
+    }
+    let rooms = Arc::new(Rooms::new(
+        config,
+        maybe_words,
+        blocklist,
+        stats.clone(),
+        bans,
+        saved_rooms,
+        metrics,
+    ));
+    if let Some(port) = canvas_port {
+        let rooms = rooms.clone();
+        let page: http::Page = Box::new(move |params| {
+            let rooms = rooms.clone();
+            async move { rooms.canvas_png(&params).await }.boxed()
+        });
+        http_pages
+            .entry(port)
+            .or_default()
+            .push(("/canvas.png", page));
+    }
+    for (port, pages) in http_pages {
+        let http_addr = SocketAddr::new(addr.ip(), port);
+        let paths = pages.iter().map(|(path, _)| *path).collect::<Vec<_>>();
+        let endpoint = http::bind(http_addr, pages).map_err(ServerError::HttpError)?;
+        info!(%http_addr, ?paths, "serving HTTP");
+        tokio::spawn(async move {
+            if let Err(err) = endpoint.await {
+                error!("HTTP endpoint stopped: {}", err);
+            }
+        });
+    }
 
+    let flushed_stats = stats.clone();
     tokio::spawn(async move {
-        server_state.run(srv_event_recv).await.unwrap();
+        let mut flush_interval = tokio::time::interval(STATS_FLUSH_INTERVAL);
+        loop {
+            flush_interval.tick().await;
+            if let Err(err) = flushed_stats.lock().unwrap().flush() {
+                error!("could not save the player stats: {}", err);
+            }
+        }
     });
 
-    while let Ok((stream, _)) = server_listener.accept().await {
-        let peer = stream.peer_addr().expect("Peer didn't have an address");
-        tokio::spawn(handle_connection(peer, stream, srv_event_send.clone()));
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let accepted = tokio::select! {
+            accepted = server_listener.accept() => accepted,
+            _ = &mut ctrl_c => break,
+        };
+        // a failed accept, e.g. because we ran out of file descriptors, only affects that one client
+        let (stream, peer) = match accepted {
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!("could not accept a connection: {}", err);
+                continue;
+            }
+        };
+        // banned addresses don't even get to the handshake
+        if rooms.bans.lock().unwrap().is_banned(peer.ip()) {
+            info!(%peer, "refusing a banned address");
+            continue;
+        }
+        let rooms = rooms.clone();
+        // the username is only known once the client joined
+        let span = tracing::info_span!("connection", %peer, username = tracing::field::Empty);
+        let tls_acceptor = tls_acceptor.clone();
+        tokio::spawn(
+            async move {
+                let result = match tls_acceptor {
+                    Some(tls_acceptor) => match tls_acceptor.accept(stream).await {
+                        Ok(stream) => handle_connection(stream, rooms, peer).await,
+                        Err(err) => {
+                            warn!("TLS handshake failed: {}", err);
+                            Ok(())
+                        }
+                    },
+                    None => handle_connection(stream, rooms, peer).await,
+                };
+                if let Err(err) = result {
+                    warn!("connection failed: {}", err);
+                }
+            }
+            .instrument(span),
+        );
+    }
+
+    info!("shutting down");
+    let snapshots = rooms.shutdown().await;
+    stats.lock().unwrap().flush()?;
+    if let Some(path) = &resume_file {
+        Snapshot::new(snapshots).save(path)?;
     }
+    // give the connections a moment to send the close frames
+    tokio::time::delay_for(SHUTDOWN_GRACE_PERIOD).await;
     Ok(())
 }
 
-async fn handle_connection(
-    peer: SocketAddr,
-    stream: TcpStream,
-    mut srv_event_send: tokio::sync::mpsc::Sender<ServerEvent>,
-) -> Result<()> {
-    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
-    println!("new WebSocket connection: {}", peer);
+/// removes the players of a restored game that didn't rejoin, as they can't take their turns
+fn remove_absent_players(
+    state: &mut SkribblState,
+    sessions: &HashMap<Username, UserSession>,
+    disconnected_players: &HashMap<Username, DisconnectedPlayer>,
+) {
+    let (absent_drawer, absent_guessers): (Vec<_>, Vec<_>) = state
+        .player_states
+        .keys()
+        .filter(|username| {
+            !sessions.contains_key(*username) && !disconnected_players.contains_key(*username)
+        })
+        .cloned()
+        .partition(|username| state.is_drawing(username));
+    for username in absent_guessers {
+        state.remove_user(&username);
+    }
+    // only now, so the next turn doesn't go to someone who is about to be removed
+    for username in absent_drawer {
+        state.remove_user(&username);
+    }
+}
+
+/// reads the certificate chain and the private key, accepting both PKCS8 and RSA keys
+fn load_tls_acceptor(files: &TlsFiles) -> Result<TlsAcceptor> {
+    let mut cert_reader = BufReader::new(std::fs::File::open(&files.cert_file)?);
+    let cert_chain = certs(&mut cert_reader)
+        .map_err(|_| ServerError::TlsError("Could not parse the certificate".to_string()))?;
+
+    let mut key_reader = BufReader::new(std::fs::File::open(&files.key_file)?);
+    let mut keys = pkcs8_private_keys(&mut key_reader)
+        .map_err(|_| ServerError::TlsError("Could not parse the private key".to_string()))?;
+    if keys.is_empty() {
+        let mut key_reader = BufReader::new(std::fs::File::open(&files.key_file)?);
+        keys = rsa_private_keys(&mut key_reader)
+            .map_err(|_| ServerError::TlsError("Could not parse the private key".to_string()))?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| ServerError::TlsError("No private key found".to_string()))?;
+
+    let mut config = TlsConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(cert_chain, key)
+        .map_err(|err| ServerError::TlsError(err.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn handle_connection<S>(stream: S, rooms: Arc<Rooms>, peer: SocketAddr) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // no client message has more text than a chat message, so larger frames are refused
+    // before they are read, instead of only once they were received
+    let max_frame_size =
+        rooms.config.max_message_length * MAX_BYTES_PER_CHAR + JOIN_REQUEST_OVERHEAD;
+    let ws_config = WebSocketConfig {
+        max_send_queue: None,
+        max_message_size: Some(max_frame_size),
+        max_frame_size: Some(max_frame_size),
+    };
+    let ws_stream = tokio_tungstenite::accept_async_with_config(stream, Some(ws_config)).await?;
+    info!("new WebSocket connection");
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    // first, wait for the client to send his username
-    let username: Username = loop {
-        let msg = ws_receiver
-            .next()
-            .await
-            .expect("No username message received")?;
-        if let tungstenite::Message::Text(username) = msg {
-            break username.into();
+    // first, wait for the client to send a join request the server accepts.
+    // If it's rejected, the client may try again with a different one.
+    let (username, room, codec, mut srv_event_send, mut session_msg_recv, mut session_close_recv) = loop {
+        let request = loop {
+            match ws_receiver.next().await {
+                Some(Ok(tungstenite::Message::Text(request))) => break request,
+                Some(Ok(tungstenite::Message::Close(_))) | None => {
+                    info!("disconnected before joining");
+                    return Ok(());
+                }
+                Some(Err(err)) => {
+                    warn!("disconnected before joining: {}", err);
+                    return Ok(());
+                }
+                // ignore anything else, like pings, until we get a join request
+                Some(Ok(_)) => {}
+            }
+        };
+
+        let max_request_length = rooms.config.max_message_length + JOIN_REQUEST_OVERHEAD;
+        let request = if request.len() > max_request_length {
+            Err("The join request is too long".to_string())
+        } else {
+            serde_json::from_str::<JoinRequest>(&request)
+                .map_err(|_| "Invalid join request".to_string())
         }
-    };
+        .and_then(|request| {
+            Username::sanitized(&request.username).map(|username| (username, request))
+        });
 
-    let (session_msg_send, mut session_msg_recv) = tokio::sync::mpsc::channel(1);
-    let (session_close_send, mut session_close_recv) = tokio::sync::mpsc::channel(1);
+        let rejection = match request {
+            Err(reason) => JoinRejection::Retry(reason),
+            Ok((_, request)) if request.protocol_version != PROTOCOL_VERSION => {
+                JoinRejection::Close("incompatible version".to_string())
+            }
+            Ok((username, request)) => {
+                let room = request.room.clone();
+                let codec = request.codec;
+                let (session_msg_send, session_msg_recv) =
+                    tokio::sync::mpsc::channel(rooms.config.session_buffer);
+                let (session_close_send, session_close_recv) = tokio::sync::mpsc::channel(1);
 
-    // then, create a session and send that session to the server's main thread
-    let session = UserSession::new(username.clone(), session_msg_send, session_close_send);
-    srv_event_send
-        .send(ServerEvent::UserJoined(session))
-        .await?;
+                // then, create a session and send that session to the room's main thread
+                let session = UserSession::new(
+                    username.clone(),
+                    session_msg_send,
+                    session_close_send,
+                    request.spectator,
+                    peer.ip(),
+                    &rooms.config,
+                );
+                let (join_send, join_recv) = oneshot::channel();
+                let mut srv_event_send = rooms.join(&room).await;
+                srv_event_send
+                    .send(ServerEvent::UserJoined(Box::new(JoinAttempt {
+                        session,
+                        request,
+                        join_send,
+                    })))
+                    .await?;
+
+                match join_recv.await {
+                    Ok(Ok(())) => {
+                        break (
+                            username,
+                            room,
+                            codec,
+                            srv_event_send,
+                            session_msg_recv,
+                            session_close_recv,
+                        )
+                    }
+                    Ok(Err(rejection)) => {
+                        rooms.leave(&room).await;
+                        rejection
+                    }
+                    // the room's main thread is gone, so there is nothing left to join
+                    Err(_) => {
+                        rooms.leave(&room).await;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        let (reason, should_close) = match rejection {
+            JoinRejection::Retry(reason) => (reason, false),
+            JoinRejection::Close(reason) => (reason, true),
+        };
+        info!(%reason, "join rejected");
+        // rejections are always JSON, as the client might not have sent a valid codec
+        let msg = Codec::Json.encode(&ToClientMsg::JoinRejected(reason))?;
+        ws_sender.send(msg).await?;
+        if should_close {
+            ws_sender.send(tungstenite::Message::Close(None)).await?;
+            return Ok(());
+        }
+    };
+
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    info!(%room, "joined");
 
     // TODO look at stream forwarding for this...
     // asynchronously read messages that the main server thread wants
     // to send to this client and forward them to the WS client
-    let send_thread = tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                maybe_msg = session_msg_recv.recv() => match maybe_msg {
-                    Some(msg) => {
-                        let msg = serde_json::to_string(&msg).expect("Could not serialize msg");
-                        let result = ws_sender.send(tungstenite::Message::Text(msg)).await;
-                        if let Err(_) = result {
+    // and ping the client regularly, so we notice if it disappeared without closing the connection
+    let (mut pong_send, mut pong_recv) = tokio::sync::mpsc::channel::<Vec<u8>>(1);
+    let send_thread = tokio::spawn(
+        async move {
+            let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = ping_interval.tick() => {
+                        let result = ws_sender.send(tungstenite::Message::Ping(Vec::new())).await;
+                        if result.is_err() {
+                            break result;
+                        }
+                    }
+                    Some(payload) = pong_recv.recv() => {
+                        let result = ws_sender.send(tungstenite::Message::Pong(payload)).await;
+                        if result.is_err() {
                             break result;
                         }
                     }
-                    // if the msg received is None, all senders have been closed, so we can finish
-                    None => {
+                    maybe_msg = session_msg_recv.recv() => match maybe_msg {
+                        Some(msg) => {
+                            let msg = match msg.encode(codec) {
+                                Ok(msg) => msg,
+                                Err(err) => {
+                                    error!("could not encode a message: {}", err.0);
+                                    continue;
+                                }
+                            };
+                            let result = ws_sender.send(msg).await;
+                            if let Err(_) = result {
+                                break result;
+                            }
+                        }
+                        // if the msg received is None, all senders have been closed, so we can finish
+                        None => {
+                            ws_sender.send(tungstenite::Message::Close(None)).await?;
+                            break Ok(());
+                        }
+                    },
+                    Some(()) = session_close_recv.recv() => {
+                        // send what was queued before closing, like why the client was kicked
+                        while let Ok(msg) = session_msg_recv.try_recv() {
+                            if let Ok(msg) = msg.encode(codec) {
+                                ws_sender.send(msg).await?;
+                            }
+                        }
                         ws_sender.send(tungstenite::Message::Close(None)).await?;
                         break Ok(());
                     }
-                },
-                _ = session_close_recv.recv() => {
-                    ws_sender.send(tungstenite::Message::Close(None)).await?;
-                    break Ok(());
                 }
             }
         }
-    });
+        .instrument(tracing::Span::current()),
+    );
 
     // TODO look at stream forwarding for this
-    // forward other events to the main server thread
+    // forward websocket messages from the client to the room's main thread,
+    // until it closes the connection or stops answering pings
+    let mut last_pong = Instant::now();
+    let mut pong_check_interval = tokio::time::interval(PING_INTERVAL);
     loop {
-        let delay = Delay::new(Duration::from_millis(500));
-        tokio::select! {
-            // every 100ms, send a tick event to the main server thread
-            _ = delay => srv_event_send.send(ServerEvent::Tick).await?,
-
-            // Websocket messages from the client
-            msg = ws_receiver.next() => match msg {
-                Some(Ok(tungstenite::Message::Text(msg))) => match serde_json::from_str(&msg) {
-                    Ok(Some(msg)) => {
-                        srv_event_send
-                            .send(ServerEvent::ToServerMsg(username.clone(), msg))
-                            .await?;
-                    }
-                    Ok(None) => {
-                        break;
-                    }
-                    Err(err) => {
-                        eprintln!("{} (msg was: {})", err, msg);
-                    }
-                },
-                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
-                _ => {}
+        let maybe_msg = tokio::select! {
+            maybe_msg = ws_receiver.next() => maybe_msg,
+            _ = pong_check_interval.tick() => {
+                if last_pong.elapsed() > rooms.config.pong_timeout {
+                    info!("stopped answering pings");
+                    break;
+                }
+                continue;
+            }
+        };
+        let msg = match maybe_msg {
+            Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+            // a client that pings is still there, even if its pong to our last ping is late.
+            // Pongs may be sent unasked, so it doesn't matter if tungstenite answered the ping as well.
+            // If the last pong wasn't sent yet, the client gets that one
+            Some(Ok(tungstenite::Message::Ping(payload))) => {
+                last_pong = Instant::now();
+                let _ = pong_send.try_send(payload);
+                continue;
+            }
+            Some(Ok(tungstenite::Message::Pong(_))) => {
+                last_pong = Instant::now();
+                continue;
+            }
+            Some(Ok(msg)) => msg,
+        };
+        match decode_msg(&msg) {
+            Some(Ok(Some(msg))) => {
+                rooms.metrics.client_messages.inc();
+                let evt = ServerEvent::ToServerMsg(username.clone(), msg);
+                if srv_event_send.send(evt).await.is_err() {
+                    break;
+                }
             }
+            Some(Ok(None)) => break,
+            Some(Err(DecodeError::VersionMismatch(version))) => {
+                warn!(
+                    "client uses protocol version {}, but we use {}",
+                    version, PROTOCOL_VERSION
+                );
+                break;
+            }
+            Some(Err(DecodeError::Invalid(err))) => {
+                warn!("{} (msg was: {})", err, msg);
+            }
+            None => {}
         }
     }
 
     drop(send_thread);
-    srv_event_send.send(ServerEvent::UserLeft(username)).await?;
+    info!("left");
+    // a player may keep their place for a while, and the room has to stay open for them to return,
+    // even if every connection to it dropped
+    let (membership_send, membership_recv) = oneshot::channel();
+    let _ = srv_event_send
+        .send(ServerEvent::UserLeft(username, membership_send))
+        .await;
+    drop(srv_event_send);
+    let _ = membership_recv.await;
+    rooms.leave(&room).await;
     Ok(())
 }
 
-pub fn read_words_file(path: &PathBuf) -> Result<Vec<String>> {
+/// reads one word per line, each optionally prefixed with its difficulty, like `easy:cat`
+fn parse_words(text: &str) -> Vec<Word> {
+    text.lines()
+        .map(Word::parse)
+        .filter(|word| !word.text.is_empty())
+        .collect::<Vec<Word>>()
+}
+
+pub fn read_words_file(path: &PathBuf) -> std::io::Result<Vec<Word>> {
     let mut file = std::fs::File::open(path)?;
     let mut words = String::new();
     file.read_to_string(&mut words)?;
-    Ok(words
+    Ok(parse_words(&words))
+}
+
+/// downloads a word list in the same format as a word file
+pub async fn fetch_words(url: &str) -> std::result::Result<Vec<Word>, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(WORD_LIST_FETCH_TIMEOUT)
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    Ok(parse_words(&response.text().await?))
+}
+
+/// reads all the word lists into one list. A word in several lists is only kept the first time.
+/// Sources starting with http:// or https:// are downloaded, everything else is read as a file
+pub async fn read_word_lists(sources: &[PathBuf]) -> Result<Vec<Word>> {
+    let mut seen = HashSet::new();
+    let mut words = Vec::new();
+    for source in sources {
+        let url = source
+            .to_str()
+            .filter(|source| source.starts_with("http://") || source.starts_with("https://"));
+        let file_words = match url {
+            Some(url) => fetch_words(url)
+                .await
+                .map_err(|err| ServerError::WordListFetchError(url.to_string(), err))?,
+            None => read_words_file(source)
+                .map_err(|err| ServerError::WordFileError(source.clone(), err))?,
+        };
+        words.extend(
+            file_words
+                .into_iter()
+                .filter(|word| seen.insert(word.text.to_lowercase())),
+        );
+    }
+    Ok(words)
+}
+
+/// a chat message for a single client, sent now
+fn new_message(message: Message) -> ToClientMsg {
+    ToClientMsg::NewMessage(TimedMessage {
+        message,
+        sent_at: get_time_now_millis(),
+    })
+}
+
+/// compares two strings in a time that only depends on their length,
+/// so that the password can't be guessed character by character through timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// brings a guess or word into a form in which they can be compared: lowercase, without accents,
+/// and with words separated by single spaces, so that "Café  au lait " is the same as "cafe au lait"
+fn normalize_guess(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// replaces every blocked word in the text with asterisks, ignoring case.
+/// The blocked words have to be lowercase
+fn censor(text: &str, blocklist: &[String]) -> String {
+    let mut chars = text.chars().collect::<Vec<_>>();
+    // lowercase char by char, so that the positions in both stay the same
+    let lowercase = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+    for blocked in blocklist {
+        let blocked = blocked.chars().collect::<Vec<_>>();
+        if blocked.is_empty() || blocked.len() > lowercase.len() {
+            continue;
+        }
+        for start in 0..=(lowercase.len() - blocked.len()) {
+            if lowercase[start..start + blocked.len()] == blocked[..] {
+                chars[start..start + blocked.len()]
+                    .iter_mut()
+                    .for_each(|c| *c = '*');
+            }
+        }
+    }
+    chars.into_iter().collect()
+}
+
+/// reads the words to censor in the chat, one per line
+pub fn read_blocklist_file(path: &PathBuf) -> std::io::Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
         .lines()
-        .map(|x| x.trim().to_string())
-        .filter(|x| !x.is_empty())
-        .collect::<Vec<String>>())
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty())
+        .collect())
 }
 
-fn is_very_close_to(a: String, b: String) -> bool {
+fn is_very_close_to(a: &str, b: &str) -> bool {
     return levenshtein_distance(a, b) <= 1;
 }
 
-fn levenshtein_distance(a: String, b: String) -> usize {
+fn levenshtein_distance(a: &str, b: &str) -> usize {
     let w1 = a.chars().collect::<Vec<_>>();
     let w2 = b.chars().collect::<Vec<_>>();
 
@@ -500,3 +2891,301 @@ fn levenshtein_distance(a: String, b: String) -> usize {
     }
     matrix[b_len - 1][a_len - 1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type MsgRecv = tokio::sync::mpsc::Receiver<Arc<SharedMsg<ToClientMsg>>>;
+
+    /// a room like the one run_server starts with these words, without any connections
+    fn room_with_words(words: &[&str]) -> ServerState {
+        let config = ServerConfig::default();
+        ServerState::new(
+            GameState::Lobby(LobbyState::new(config.settings.min_ready_players)),
+            Some(words.iter().map(|word| Word::parse(word)).collect()),
+            &config,
+            Vec::new(),
+            Arc::new(std::sync::Mutex::new(StatsStore::load(None).unwrap())),
+            Arc::new(std::sync::Mutex::new(BanList::load(None).unwrap())),
+            Arc::new(Metrics::default()),
+        )
+    }
+
+    /// joins the room as a player, returning what the room sends to them
+    async fn join(state: &mut ServerState, name: &str) -> MsgRecv {
+        join_with(state, name, false, None).await
+    }
+
+    /// joins the room as a player or spectator with a terminal of the given size
+    async fn join_with(
+        state: &mut ServerState,
+        name: &str,
+        spectator: bool,
+        dimensions: Option<(usize, usize)>,
+    ) -> MsgRecv {
+        let (msg_send, msg_recv) = tokio::sync::mpsc::channel(1000);
+        let (close_send, _) = tokio::sync::mpsc::channel(1);
+        let session = UserSession::new(
+            Username::from(name.to_string()),
+            msg_send,
+            close_send,
+            spectator,
+            IpAddr::from([127, 0, 0, 1]),
+            &ServerConfig::default(),
+        );
+        let request = JoinRequest {
+            protocol_version: PROTOCOL_VERSION,
+            username: name.to_string(),
+            room: "room".to_string(),
+            password: None,
+            codec: Codec::Json,
+            spectator,
+            host_key: None,
+            reconnect_token: None,
+            dimensions,
+        };
+        let (join_send, join_recv) = oneshot::channel();
+        let attempt = JoinAttempt {
+            session,
+            request,
+            join_send,
+        };
+        state.on_user_joined(attempt).await.unwrap();
+        assert!(join_recv.await.unwrap().is_ok(), "{} couldn't join", name);
+        msg_recv
+    }
+
+    async fn send(state: &mut ServerState, name: &str, msg: ToServerMsg) {
+        let username = Username::from(name.to_string());
+        state.on_to_srv_msg(username, msg).await.unwrap();
+    }
+
+    fn chat(name: &str, text: &str) -> ToServerMsg {
+        let username = Username::from(name.to_string());
+        ToServerMsg::NewMessage(Message::UserMsg(username, text.to_string()))
+    }
+
+    /// the messages the client got since this was last called, as JSON
+    fn received(msg_recv: &mut MsgRecv) -> Vec<String> {
+        let mut msgs = Vec::new();
+        while let Ok(msg) = msg_recv.try_recv() {
+            match msg.encode(Codec::Json).unwrap() {
+                tungstenite::Message::Text(text) => msgs.push(text),
+                msg => panic!("expected a JSON message, got {:?}", msg),
+            }
+        }
+        msgs
+    }
+
+    fn got_chat_msg(msgs: &[String], text: &str) -> bool {
+        let text = serde_json::to_string(text).unwrap();
+        msgs.iter()
+            .any(|msg| msg.starts_with(r#"{"NewMessage""#) && msg.contains(&text))
+    }
+
+    fn got_system_msg(msgs: &[String], text: &str) -> bool {
+        let msg = serde_json::to_string(&Message::SystemMsg(text.to_string())).unwrap();
+        msgs.iter().any(|received| received.contains(&msg))
+    }
+
+    #[tokio::test]
+    async fn no_chat_msg_is_dropped_when_the_game_starts() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let mut a = join(&mut state, "a").await;
+        let mut b = join(&mut state, "b").await;
+
+        send(&mut state, "a", chat("a", "ready when you are")).await;
+        send(&mut state, "a", ToServerMsg::Ready).await;
+        let (a_msgs, b_msgs) = (received(&mut a), received(&mut b));
+        assert!(got_chat_msg(&a_msgs, "ready when you are"));
+        assert!(got_chat_msg(&b_msgs, "ready when you are"));
+
+        // the last player getting ready starts the game, and the next message is still chat
+        send(&mut state, "b", ToServerMsg::Ready).await;
+        assert!(matches!(state.game_state, GameState::Skribbl(_)));
+        send(&mut state, "b", chat("b", "let's go")).await;
+        let (a_msgs, b_msgs) = (received(&mut a), received(&mut b));
+        assert!(got_chat_msg(&a_msgs, "let's go"));
+        assert!(got_chat_msg(&b_msgs, "let's go"));
+    }
+
+    #[tokio::test]
+    async fn whispers_are_not_sent_as_chat_msgs() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let _a = join(&mut state, "a").await;
+        let mut b = join(&mut state, "b").await;
+        let mut c = join(&mut state, "c").await;
+
+        let whisper = Message::Whisper {
+            from: Username::from("c".to_string()),
+            to: Username::from("b".to_string()),
+            text: "psst".to_string(),
+        };
+        send(&mut state, "a", ToServerMsg::NewMessage(whisper)).await;
+        assert!(!got_chat_msg(&received(&mut b), "psst"));
+        assert!(!got_chat_msg(&received(&mut c), "psst"));
+    }
+
+    #[tokio::test]
+    async fn clients_cant_send_system_msgs() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        state.blocklist = vec!["darn".to_string()];
+        let _a = join(&mut state, "a").await;
+        let mut b = join(&mut state, "b").await;
+
+        let msg = Message::SystemMsg("darn, the server is shutting down".to_string());
+        send(&mut state, "a", ToServerMsg::NewMessage(msg)).await;
+        let msgs = received(&mut b);
+        assert!(!msgs.iter().any(|msg| msg.contains("shutting down")));
+    }
+
+    #[tokio::test]
+    async fn chat_msgs_are_sent_under_the_senders_name() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let _a = join(&mut state, "a").await;
+        let mut b = join(&mut state, "b").await;
+
+        send(&mut state, "a", chat("b", "I give up")).await;
+        let msgs = received(&mut b);
+        let from_a = serde_json::to_string(&Message::UserMsg(
+            Username::from("a".to_string()),
+            "I give up".to_string(),
+        ))
+        .unwrap();
+        assert!(msgs.iter().any(|msg| msg.contains(&from_a)));
+    }
+
+    #[tokio::test]
+    async fn spectators_dont_shrink_the_canvas() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let _a = join(&mut state, "a").await;
+        let dimensions = state.dimensions;
+        let _spectator = join_with(&mut state, "s", true, Some((1, 1))).await;
+        assert_eq!(state.dimensions, dimensions);
+    }
+
+    #[tokio::test]
+    async fn drawing_comes_back_when_the_canvas_grows() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let _a = join(&mut state, "a").await;
+        let dimensions = state.dimensions;
+        let corner = data::Coord(dimensions.0 as u16 - 1, dimensions.1 as u16 - 1);
+        let line = data::Line::new(data::Coord(0, 0), corner, CanvasColor::White, 1);
+        send(&mut state, "a", ToServerMsg::NewLine(line)).await;
+        let fill = data::Fill {
+            x: corner.0,
+            y: corner.1,
+            color: CanvasColor::White,
+        };
+        send(&mut state, "a", ToServerMsg::Fill(fill)).await;
+
+        let mut b = join_with(&mut state, "b", false, Some(MIN_DIMENSIONS)).await;
+        assert_eq!(state.dimensions, MIN_DIMENSIONS);
+        assert_eq!(state.visible_canvas_ops().len(), 1);
+        // b never got the fill, so undoing it doesn't take anything back on their canvas
+        send(&mut state, "a", ToServerMsg::UndoLine).await;
+        assert!(!received(&mut b).iter().any(|msg| msg.contains("UndoLine")));
+
+        state
+            .remove_player(&Username::from("b".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(state.dimensions, dimensions);
+        assert_eq!(state.visible_canvas_ops(), vec![CanvasOp::Line(line)]);
+    }
+
+    #[tokio::test]
+    async fn game_pauses_when_the_drawer_leaves_the_last_player() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let mut msg_recvs = HashMap::new();
+        for name in &["a", "b"] {
+            msg_recvs.insert(name.to_string(), join(&mut state, name).await);
+        }
+        send(&mut state, "a", ToServerMsg::Ready).await;
+        send(&mut state, "b", ToServerMsg::Ready).await;
+        // whoever draws first leaves
+        let drawer = match &state.game_state {
+            GameState::Skribbl(game) => game.drawing_user.clone(),
+            _ => panic!("the game didn't start"),
+        };
+        let other = if drawer.to_string() == "a" { "b" } else { "a" };
+
+        state.remove_player(&drawer).await.unwrap();
+        let game = state.game_state.skribbl_state().unwrap();
+        assert!(game.is_drawing(&Username::from(other.to_string())));
+        assert!(game.is_paused());
+        let msgs = received(msg_recvs.get_mut(other).unwrap());
+        assert!(got_system_msg(&msgs, "Waiting for more players to join..."));
+    }
+
+    #[tokio::test]
+    async fn drawer_who_cleared_the_canvas_is_idle_only_once_they_stop() {
+        let mut state = room_with_words(&["giraffe", "elephant"]);
+        let _a = join(&mut state, "a").await;
+        let _b = join(&mut state, "b").await;
+        send(&mut state, "a", ToServerMsg::Ready).await;
+        send(&mut state, "b", ToServerMsg::Ready).await;
+        let game = match &mut state.game_state {
+            GameState::Skribbl(game) => game,
+            _ => panic!("the game didn't start"),
+        };
+        game.start_turn();
+        game.choose_first_word();
+        // well past the time the drawing user has to start drawing
+        game.round_end_time -= 100;
+        let drawer = game.drawing_user.clone();
+
+        let line = data::Line::new(data::Coord(1, 1), data::Coord(5, 5), CanvasColor::White, 1);
+        state
+            .on_to_srv_msg(drawer.clone(), ToServerMsg::NewLine(line))
+            .await
+            .unwrap();
+        state
+            .on_to_srv_msg(drawer.clone(), ToServerMsg::ClearCanvas)
+            .await
+            .unwrap();
+        state.kick_idle_players().await.unwrap();
+        assert!(state.sessions.contains_key(&drawer));
+
+        let session = state.sessions.get_mut(&drawer).unwrap();
+        session.last_drawing_activity = Instant::now() - Duration::from_secs(100);
+        state.kick_idle_players().await.unwrap();
+        assert!(!state.sessions.contains_key(&drawer));
+    }
+
+    #[tokio::test]
+    async fn missing_word_file_is_an_error() {
+        let path = PathBuf::from("/nonexistent/words.txt");
+        match read_word_lists(std::slice::from_ref(&path)).await {
+            Err(ServerError::WordFileError(err_path, err)) => {
+                assert_eq!(err_path, path);
+                assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+            }
+            result => panic!("expected a WordFileError, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn guesses_are_normalized() {
+        assert_eq!(normalize_guess("Café"), normalize_guess("cafe"));
+        assert_eq!(normalize_guess("CAFÉ"), "cafe");
+        assert_eq!(normalize_guess("  ice   cream \t cone "), "ice cream cone");
+        assert_eq!(
+            normalize_guess("Crème  Brûlée"),
+            normalize_guess("creme brulee")
+        );
+        assert_ne!(normalize_guess("ice cream"), normalize_guess("icecream"));
+    }
+
+    #[test]
+    fn levenshtein_distance_counts_edits() {
+        assert_eq!(levenshtein_distance("giraffe", "giraffe"), 0);
+        assert_eq!(levenshtein_distance("giraffe", "giraffa"), 1);
+        assert_eq!(levenshtein_distance("giraffe", "giraffes"), 1);
+        assert_eq!(levenshtein_distance("", "cat"), 3);
+        assert_eq!(levenshtein_distance("cat", ""), 3);
+        assert!(is_very_close_to("giraffe", "girafe"));
+        assert!(!is_very_close_to("giraffe", "girafa"));
+    }
+}
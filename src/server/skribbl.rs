@@ -1,28 +1,221 @@
 use super::server::ROUND_DURATION;
 use crate::client::Username;
-use rand::{prelude::IteratorRandom, seq::SliceRandom};
+use rand::{prelude::IteratorRandom, Rng};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::{cmp::max, time};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::time::{self, Duration};
 use time::{SystemTime, UNIX_EPOCH};
 
+/// how many words the drawing user gets to choose from
+pub const WORD_CHOICE_COUNT: usize = 3;
+
+/// how long the drawing user has to choose a word before the first option is picked
+pub const WORD_CHOICE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// default points for guessing the word right at the start of a turn
+pub const GUESS_POINTS: u32 = 100;
+
+/// default fraction of the guess points that is lost by the time the turn is over
+pub const GUESS_POINTS_DECAY: f64 = 0.9;
+
+/// default fraction of the guess points the drawing user gets for every correct guess
+pub const DRAWER_SHARE: f64 = 0.5;
+
+/// default for the most points the drawing user can get from correct guesses in a single turn
+pub const MAX_DRAWER_POINTS: u32 = 150;
+
+/// default extra points, in percent, for every word a player guessed in a row before this one
+pub const STREAK_BONUS_PERCENT: u32 = 10;
+
+/// default for the most extra points, in percent, a streak can give
+pub const MAX_STREAK_BONUS_PERCENT: u32 = 50;
+
+/// default time a player may be idle before they are removed, in seconds
+pub const IDLE_TIMEOUT: u64 = 180;
+
+/// default time between two turns, in seconds
+pub const TURN_BREAK: u64 = 3;
+
+/// how many of the last drawn words aren't offered again, if there are enough other words
+pub const RECENT_WORD_COUNT: usize = 20;
+
+/// how much more likely words of the preferred difficulty are picked than other words
+const PREFERRED_DIFFICULTY_WEIGHT: f64 = 3.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("unknown difficulty {}", s)),
+        }
+    }
+}
+
+/// a word that can be drawn, as read from the word file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    pub difficulty: Difficulty,
+}
+
+impl Word {
+    /// reads a line of the word file, which may start with a difficulty, like `hard:photosynthesis`.
+    /// Lines without one are normal words
+    pub fn parse(line: &str) -> Word {
+        if let Some((prefix, text)) = line.split_once(':') {
+            if let Ok(difficulty) = prefix.trim().parse() {
+                return Word {
+                    text: text.trim().to_string(),
+                    difficulty,
+                };
+            }
+        }
+        Word {
+            text: line.trim().to_string(),
+            difficulty: Difficulty::Normal,
+        }
+    }
+}
+
+/// rules of a skribbl game that the host can configure
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameSettings {
+    /// how long each turn lasts
+    pub round_duration: Duration,
+
+    /// fractions of the turn after which another letter of the word is revealed
+    pub hint_thresholds: Vec<f64>,
+
+    /// how long a player may not send anything before they are removed from the game
+    pub idle_timeout: Duration,
+
+    /// how many players in the lobby have to be ready for the game to start on its own
+    pub min_ready_players: usize,
+
+    /// with fewer players than this, the game pauses until more join
+    pub min_players: usize,
+
+    /// how often every player gets to draw before the game is over
+    pub total_rounds: u32,
+
+    /// if set, words of this difficulty come up more often than others
+    pub difficulty: Option<Difficulty>,
+
+    /// a player is kicked once more than this fraction of the other players voted for it
+    pub vote_kick_fraction: f64,
+
+    /// how long the game waits between two turns, so everyone can read what the word was
+    pub turn_break: Duration,
+
+    /// how many points players get for guessing and drawing
+    pub scoring: ScoringSettings,
+}
+
+/// how many points players get for guessing and drawing
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScoringSettings {
+    /// points for guessing the word right at the start of a turn
+    pub guess_points: u32,
+
+    /// fraction of the guess points that is lost by the time the turn is over
+    pub guess_points_decay: f64,
+
+    /// fraction of the guess points the drawing user gets for every correct guess
+    pub drawer_share: f64,
+
+    /// the most points the drawing user can get from correct guesses in a single turn
+    pub max_drawer_points: u32,
+
+    /// extra points, in percent, for every word a player guessed in a row before this one
+    pub streak_bonus_percent: u32,
+
+    /// the most extra points, in percent, a streak can give
+    pub max_streak_bonus_percent: u32,
+}
+
+impl Default for ScoringSettings {
+    fn default() -> Self {
+        ScoringSettings {
+            guess_points: GUESS_POINTS,
+            guess_points_decay: GUESS_POINTS_DECAY,
+            drawer_share: DRAWER_SHARE,
+            max_drawer_points: MAX_DRAWER_POINTS,
+            streak_bonus_percent: STREAK_BONUS_PERCENT,
+            max_streak_bonus_percent: MAX_STREAK_BONUS_PERCENT,
+        }
+    }
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            round_duration: Duration::from_secs(ROUND_DURATION),
+            hint_thresholds: vec![0.4, 0.7],
+            idle_timeout: Duration::from_secs(IDLE_TIMEOUT),
+            min_ready_players: 2,
+            min_players: 2,
+            total_rounds: 3,
+            difficulty: None,
+            vote_kick_fraction: 0.5,
+            turn_break: Duration::from_secs(TURN_BREAK),
+            scoring: ScoringSettings::default(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SkribblState {
     current_word: String,
     revealed_characters: Vec<usize>,
 
+    /// the words the drawing user can choose from. While the drawing user is choosing,
+    /// the current word is empty and round_end_time is the deadline for choosing.
+    word_choices: Vec<String>,
+
     /// the currently drawing user
     pub drawing_user: Username,
 
     /// players which didn't draw yet in the current round.
     pub remaining_users: Vec<Username>,
 
+    /// the current round, starting at 1. Every player draws once per round
+    pub round: u32,
+
     /// states of all the players
     pub player_states: HashMap<Username, PlayerState>,
 
     pub round_end_time: u64,
 
+    /// whether the game waits for the next turn to start. round_end_time is when it starts,
+    /// and the drawing user gets to choose a word only then
+    between_turns: bool,
+
+    /// while the game is paused, the seconds that were left in the turn when it was paused
+    pub paused_remaining_time: Option<u32>,
+
+    pub settings: GameSettings,
+
     pub remaining_words: Vec<String>,
+
+    /// the last drawn words, oldest first. Only the server needs these
+    #[serde(skip)]
+    recent_words: VecDeque<String>,
+
+    /// the guessers who voted to skip the current turn
+    #[serde(skip)]
+    skip_votes: HashSet<Username>,
 }
 
 impl SkribblState {
@@ -39,34 +232,239 @@ impl SkribblState {
         self.revealed_characters = Vec::new();
     }
 
-    /// reveals a random character, as long as that doesn't reveal half of the word
-    pub fn reveal_random_char(&mut self) {
-        if self.revealed_characters.len() < self.current_word.len() / 2 {
-            let mut rng = rand::thread_rng();
-            self.revealed_characters
-                .push((0..self.current_word.len()).choose(&mut rng).unwrap());
+    pub fn word_choices(&self) -> &[String] {
+        self.word_choices.as_ref()
+    }
+
+    pub fn is_choosing_word(&self) -> bool {
+        self.current_word.is_empty()
+    }
+
+    pub fn is_between_turns(&self) -> bool {
+        self.between_turns
+    }
+
+    /// ends the break between two turns, letting the drawing user choose a word
+    pub fn start_turn(&mut self) {
+        self.between_turns = false;
+        self.offer_word_choices();
+    }
+
+    /// takes the next few words from the remaining words and lets the drawing user choose
+    /// between them. The round clock only starts once a word has been chosen.
+    fn offer_word_choices(&mut self) {
+        // choices nobody picked, because the drawing user left, go back to the remaining words
+        let unpicked = std::mem::take(&mut self.word_choices);
+        self.remaining_words.extend(unpicked);
+        // words drawn lately go to the back, so they're only offered if there aren't enough others.
+        // Of those, the ones drawn longest ago come first
+        let recent_words = &self.recent_words;
+        let (fresh, mut recent) = self
+            .remaining_words
+            .drain(..)
+            .partition::<Vec<_>, _>(|word| !recent_words.contains(word));
+        recent.sort_by_key(|word| recent_words.iter().rposition(|recent| recent == word));
+        self.remaining_words = fresh.into_iter().chain(recent).collect();
+        let choice_cnt = WORD_CHOICE_COUNT.min(self.remaining_words.len());
+        self.word_choices = self.remaining_words.drain(..choice_cnt).collect();
+        self.set_current_word(String::new());
+        self.round_end_time = get_time_now() + WORD_CHOICE_TIMEOUT.as_secs();
+    }
+
+    /// starts the turn with the given word, if it was one of the offered choices.
+    /// All the offered words go back to the end of the remaining words,
+    /// but the chosen one counts as recent until RECENT_WORD_COUNT other words were drawn.
+    pub fn choose_word(&mut self, word: &str) -> bool {
+        if !self.word_choices.iter().any(|choice| choice == word) {
+            return false;
+        }
+        let (chosen, unchosen) = self
+            .word_choices
+            .drain(..)
+            .partition::<Vec<_>, _>(|choice| choice == word);
+        let chosen = chosen.into_iter().next().unwrap();
+        self.remaining_words.extend(unchosen);
+        self.remaining_words.push(chosen.clone());
+        self.recent_words.push_back(chosen.clone());
+        if self.recent_words.len() > RECENT_WORD_COUNT {
+            self.recent_words.pop_front();
+        }
+        self.set_current_word(chosen);
+        self.round_end_time = get_time_now() + self.settings.round_duration.as_secs();
+        true
+    }
+
+    /// replaces the word of the current turn, even if the drawing user is still choosing one.
+    /// Everyone has to guess again, and the round clock starts over. Not possible between turns
+    pub fn set_word(&mut self, word: String) -> bool {
+        if self.between_turns {
+            return false;
+        }
+        let unpicked = std::mem::take(&mut self.word_choices);
+        self.remaining_words.extend(unpicked);
+        self.player_states
+            .iter_mut()
+            .for_each(|(_, player)| player.has_solved = false);
+        self.set_current_word(word);
+        let round_duration = self.settings.round_duration.as_secs();
+        match self.paused_remaining_time {
+            Some(_) => self.paused_remaining_time = Some(round_duration as u32),
+            None => self.round_end_time = get_time_now() + round_duration,
         }
+        true
     }
 
-    /// returns the placeholder chars for the current word, with the revealed characters revealed.
+    /// sets every player's score and streak back to zero, leaving the turn as it is
+    pub fn reset_scores(&mut self) {
+        self.player_states.iter_mut().for_each(|(_, player)| {
+            player.score = 0;
+            player.last_points = 0;
+            player.streak = 0;
+        });
+    }
+
+    /// adds a word to the ones that may still be offered, at a random place
+    pub fn add_word(&mut self, word: String) {
+        let idx = rand::thread_rng().gen_range(0, self.remaining_words.len() + 1);
+        self.remaining_words.insert(idx, word);
+    }
+
+    /// stops offering the word. Words that are already offered or being drawn stay
+    pub fn remove_word(&mut self, word: &str) {
+        let word = word.to_lowercase();
+        self.remaining_words
+            .retain(|remaining| remaining.to_lowercase() != word);
+    }
+
+    /// picks the first word choice for the drawing user, if they didn't choose in time.
+    pub fn choose_first_word(&mut self) -> bool {
+        match self.word_choices.first().cloned() {
+            Some(word) => self.choose_word(&word),
+            None => false,
+        }
+    }
+
+    /// reveals a random hidden letter, as long as that doesn't reveal half of the word.
+    /// returns whether a letter was revealed.
+    pub fn reveal_random_char(&mut self) -> bool {
+        let letter_cnt = self
+            .current_word
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .count();
+        if self.revealed_characters.len() >= letter_cnt / 2 {
+            return false;
+        }
+        let mut rng = rand::thread_rng();
+        let hidden_letter = self
+            .current_word
+            .chars()
+            .enumerate()
+            .filter(|(idx, c)| c.is_alphanumeric() && !self.revealed_characters.contains(idx))
+            .map(|(idx, _)| idx)
+            .choose(&mut rng);
+        match hidden_letter {
+            Some(idx) => {
+                self.revealed_characters.push(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// returns the current word with every letter that wasn't revealed yet replaced by an underscore.
+    /// Spaces and punctuation are always shown.
     pub fn hinted_current_word(&self) -> String {
         self.current_word
             .chars()
             .enumerate()
             .map(|(idx, c)| {
-                if self.revealed_characters.contains(&(idx as usize)) {
+                if !c.is_alphanumeric() || self.revealed_characters.contains(&idx) {
                     c
-                } else if c.is_whitespace() {
-                    ' '
                 } else {
-                    '?'
+                    '_'
                 }
             })
             .collect()
     }
 
+    /// how many letters should be revealed by now, according to the hint thresholds
+    pub fn due_hint_count(&self) -> usize {
+        let round_duration = self.settings.round_duration.as_secs_f64();
+        let elapsed_ratio = 1.0 - self.remaining_time() as f64 / round_duration;
+        self.settings
+            .hint_thresholds
+            .iter()
+            .filter(|threshold| elapsed_ratio >= **threshold)
+            .count()
+    }
+
+    /// returns a copy of this state that is safe to send to the given user:
+    /// the current word is masked unless they are the one drawing it,
+    /// and the upcoming words are never included.
+    pub fn for_user(&self, username: &Username) -> SkribblState {
+        if !self.is_drawing(username) {
+            return self.for_guessers();
+        }
+        let mut state = self.clone();
+        state.remaining_words = Vec::new();
+        state
+    }
+
+    /// the state as everyone but the drawing user sees it
+    pub fn for_guessers(&self) -> SkribblState {
+        let mut state = self.clone();
+        state.current_word = self.hinted_current_word();
+        state.word_choices = Vec::new();
+        state.remaining_words = Vec::new();
+        state
+    }
+
+    /// seconds left in the current turn, which is 0 once the round end time has passed.
+    /// The time doesn't run out while the game is paused.
     pub fn remaining_time(&self) -> u32 {
-        max(0, self.round_end_time as i64 - get_time_now() as i64) as u32
+        match self.paused_remaining_time {
+            Some(remaining_time) => remaining_time,
+            None => self.round_end_time.saturating_sub(get_time_now()) as u32,
+        }
+    }
+
+    /// how long the current turn has been going on, since the word was chosen
+    pub fn elapsed_time(&self) -> Duration {
+        self.settings
+            .round_duration
+            .checked_sub(Duration::from_secs(self.remaining_time() as u64))
+            .unwrap_or_default()
+    }
+
+    /// when the turn ends, in milliseconds since the unix epoch. None while the game is paused
+    pub fn round_end_millis(&self) -> Option<u64> {
+        match self.paused_remaining_time {
+            Some(_) => None,
+            None => Some(self.round_end_time * 1000),
+        }
+    }
+
+    pub fn is_time_up(&self) -> bool {
+        !self.is_paused() && get_time_now() >= self.round_end_time
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_remaining_time.is_some()
+    }
+
+    /// stops the clock of the current turn
+    pub fn pause(&mut self) {
+        if !self.is_paused() {
+            self.paused_remaining_time = Some(self.remaining_time());
+        }
+    }
+
+    /// starts the clock again, with the time that was left when the game was paused
+    pub fn resume(&mut self) {
+        if let Some(remaining_time) = self.paused_remaining_time.take() {
+            self.round_end_time = get_time_now() + remaining_time as u64;
+        }
     }
 
     pub fn did_all_solve(&self) -> bool {
@@ -75,21 +473,39 @@ impl SkribblState {
             .all(|(username, player)| player.has_solved || username == &self.drawing_user)
     }
 
+    /// how many players guessed the word, and how many may guess it at all
+    pub fn guess_progress(&self) -> (usize, usize) {
+        let guessers = self
+            .player_states
+            .iter()
+            .filter(|(username, _)| **username != self.drawing_user);
+        let solved = guessers.clone().filter(|(_, x)| x.has_solved).count();
+        (solved, guessers.count())
+    }
+
+    /// credits the drawing user with their share of the points of a correct guess,
+    /// up to the most points a drawing user may get per turn.
+    pub fn award_drawer(&mut self, guess_points: u32) {
+        let scoring = &self.settings.scoring;
+        if let Some(drawer) = self.player_states.get_mut(&self.drawing_user) {
+            let points = ((guess_points as f64 * scoring.drawer_share) as u32)
+                .min(scoring.max_drawer_points.saturating_sub(drawer.last_points));
+            drawer.score = drawer.score.saturating_add(points);
+            drawer.last_points = drawer.last_points.saturating_add(points);
+        }
+    }
+
     pub fn has_solved(&self, username: &Username) -> bool {
         self.player_states.get(username).map(|x| x.has_solved) == Some(true)
     }
 
+    /// removes the player from the game. If they were drawing, the turn passes to the next player
     pub fn remove_user(&mut self, username: &Username) {
         self.player_states.remove(username);
-        let left_player_idx = self
-            .remaining_users
-            .iter()
-            .enumerate()
-            .find(|(_, name)| name == &username)
-            .map(|x| x.0);
-        // TODO check if idx = 0
-        if let Some(idx) = left_player_idx {
-            self.remaining_users.remove(idx);
+        self.skip_votes.remove(username);
+        self.remaining_users.retain(|name| name != username);
+        if self.is_drawing(username) {
+            self.next_turn();
         }
     }
 
@@ -104,7 +520,9 @@ impl SkribblState {
         self.drawing_user == *username
     }
     pub fn can_guess(&self, username: &Username) -> bool {
-        !self.is_drawing(username)
+        !self.is_choosing_word()
+            && !self.is_paused()
+            && !self.is_drawing(username)
             && !self
                 .player_states
                 .get(username)
@@ -112,48 +530,118 @@ impl SkribblState {
                 .unwrap_or(false)
     }
 
-    pub fn next_turn(&mut self) -> &Username {
-        let remaining_time = self.remaining_time();
-        self.player_states
-            .get_mut(&self.drawing_user)
-            .map(|drawing_user| {
-                drawing_user.score += 50;
-                drawing_user.on_solve(remaining_time);
-            });
+    /// counts a guesser's vote to skip the current turn.
+    /// Returns how many guessers voted, and how many votes it takes to skip the turn
+    pub fn vote_skip(&mut self, username: &Username) -> (usize, usize) {
+        if !self.is_drawing(username) && self.player_states.contains_key(username) {
+            self.skip_votes.insert(username.clone());
+        }
+        let guesser_cnt = self.player_states.len().saturating_sub(1);
+        (self.skip_votes.len(), guesser_cnt / 2 + 1)
+    }
 
-        let new_word = self.remaining_words.remove(0);
-        self.set_current_word(new_word);
-        self.round_end_time = get_time_now() + ROUND_DURATION;
-        if self.remaining_users.len() == 0 {
+    /// whether the last round has been played
+    pub fn is_game_over(&self) -> bool {
+        self.round > self.settings.total_rounds
+    }
+
+    /// every player with their score, best first. Players with the same score are ordered by name
+    pub fn scoreboard(&self) -> Vec<(Username, u32)> {
+        let mut scores = self
+            .player_states
+            .iter()
+            .map(|(username, player)| (username.clone(), player.score))
+            .collect::<Vec<_>>();
+        scores.sort_by(|(name_a, score_a), (name_b, score_b)| {
+            score_b.cmp(score_a).then(name_a.cmp(name_b))
+        });
+        scores
+    }
+
+    /// lets the next player draw. If everyone drew in this round, the next round starts,
+    /// unless it was the last one; then the game is over and nobody gets to draw.
+    /// Without any players left, the turn stays with the last drawer.
+    pub fn next_turn(&mut self) -> &Username {
+        if self.player_states.is_empty() {
+            return &self.drawing_user;
+        }
+        if self.remaining_users.is_empty() {
+            self.round += 1;
+            if self.is_game_over() {
+                return &self.drawing_user;
+            }
             self.remaining_users = self.player_states.keys().cloned().collect();
         }
-        self.drawing_user = self.remaining_users.remove(0);
+        let previous_drawer =
+            std::mem::replace(&mut self.drawing_user, self.remaining_users.remove(0));
         self.player_states
             .iter_mut()
-            .for_each(|(_, player)| player.has_solved = false);
+            .for_each(|(username, player)| {
+                if !player.has_solved && *username != previous_drawer {
+                    player.streak = 0;
+                }
+                player.has_solved = false;
+                player.last_points = 0;
+            });
+        self.skip_votes.clear();
+        if self.settings.turn_break.is_zero() {
+            self.offer_word_choices();
+        } else {
+            // choices nobody picked, because the drawing user left, go back to the remaining words
+            let unpicked = std::mem::take(&mut self.word_choices);
+            self.remaining_words.extend(unpicked);
+            self.set_current_word(String::new());
+            self.between_turns = true;
+            self.round_end_time = get_time_now() + self.settings.turn_break.as_secs();
+        }
         &self.drawing_user
     }
 
-    pub fn new(users: Vec<Username>, mut words: Vec<String>) -> Self {
-        let mut rng = rand::thread_rng();
-        words.shuffle(&mut rng);
-        let current_word = words.remove(0);
+    pub fn new(users: Vec<Username>, words: Vec<Word>, settings: GameSettings) -> Self {
+        let words = shuffle_words(words, settings.difficulty);
         let mut state = SkribblState {
-            current_word: current_word.clone(),
+            current_word: String::new(),
             revealed_characters: Vec::new(),
+            word_choices: Vec::new(),
             drawing_user: users[0].clone(),
             remaining_users: users.iter().cloned().skip(1).collect::<Vec<_>>(),
+            round: 1,
             player_states: HashMap::new(),
-            round_end_time: get_time_now() + ROUND_DURATION,
+            round_end_time: get_time_now() + settings.round_duration.as_secs(),
+            paused_remaining_time: None,
+            between_turns: false,
+            settings,
             remaining_words: words,
+            recent_words: VecDeque::new(),
+            skip_votes: HashSet::new(),
         };
         for user in users {
             state.player_states.insert(user, PlayerState::default());
         }
+        state.offer_word_choices();
         state
     }
 }
 
+/// shuffles the words, putting words of the preferred difficulty towards the front
+fn shuffle_words(words: Vec<Word>, preferred: Option<Difficulty>) -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    // weighted random sampling: a random key in [0, 1) raised to 1/weight, highest key first
+    let mut keyed_words = words
+        .into_iter()
+        .map(|word| {
+            let weight = if Some(word.difficulty) == preferred {
+                PREFERRED_DIFFICULTY_WEIGHT
+            } else {
+                1.0
+            };
+            (rng.gen::<f64>().powf(1.0 / weight), word.text)
+        })
+        .collect::<Vec<_>>();
+    keyed_words.sort_by(|(key_a, _), (key_b, _)| key_b.partial_cmp(key_a).unwrap());
+    keyed_words.into_iter().map(|(_, word)| word).collect()
+}
+
 pub fn get_time_now() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -161,10 +649,23 @@ pub fn get_time_now() -> u64 {
         .as_secs()
 }
 
+pub fn get_time_now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PlayerState {
     pub score: u32,
     pub has_solved: bool,
+    /// the points this player got in the current turn,
+    /// either for solving the word or for drawing it
+    pub last_points: u32,
+    /// how many words in a row this player guessed. Drawing doesn't break the streak
+    #[serde(default)]
+    pub streak: u32,
 }
 
 impl Default for PlayerState {
@@ -172,17 +673,162 @@ impl Default for PlayerState {
         PlayerState {
             score: 0,
             has_solved: false,
+            last_points: 0,
+            streak: 0,
         }
     }
 }
 
 impl PlayerState {
-    pub fn on_solve(&mut self, remaining_time: u32) {
-        self.score += calculate_score_increase(remaining_time);
+    pub fn on_solve(&mut self, remaining_time: u32, settings: &GameSettings) {
+        let scoring = &settings.scoring;
+        self.streak = self.streak.saturating_add(1);
+        let bonus_percent = ((self.streak - 1).saturating_mul(scoring.streak_bonus_percent))
+            .min(scoring.max_streak_bonus_percent);
+        // the scoring settings aren't limited, so this must not overflow even for huge values
+        let points = (calculate_score_increase(remaining_time, settings) as u64)
+            .saturating_mul(100 + bonus_percent as u64)
+            / 100;
+        self.last_points = points.min(u32::MAX as u64) as u32;
+        self.score = self.score.saturating_add(self.last_points);
         self.has_solved = true;
     }
 }
 
-pub fn calculate_score_increase(remaining_time: u32) -> u32 {
-    50 + (((remaining_time as f64 / ROUND_DURATION as f64) * 100f64) as u32 / 2u32)
+/// the points for a correct guess, decaying linearly from the guess points at the start
+/// of the turn to what is left of them after the decay when the time runs out.
+pub fn calculate_score_increase(remaining_time: u32, settings: &GameSettings) -> u32 {
+    let scoring = &settings.scoring;
+    let time_ratio = (remaining_time as f64 / settings.round_duration.as_secs_f64()).min(1.0);
+    let decay = scoring.guess_points_decay.clamp(0.0, 1.0);
+    let min_points = (scoring.guess_points as f64 * (1.0 - decay)).round() as u32;
+    min_points + ((scoring.guess_points - min_points) as f64 * time_ratio) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings_with(scoring: ScoringSettings) -> GameSettings {
+        GameSettings {
+            round_duration: Duration::from_secs(100),
+            scoring,
+            ..GameSettings::default()
+        }
+    }
+
+    fn game_with(users: &[&str], words: &[&str]) -> SkribblState {
+        let users = users.iter().map(|name| Username::from(name.to_string()));
+        let words = words.iter().map(|word| Word::parse(word));
+        SkribblState::new(users.collect(), words.collect(), GameSettings::default())
+    }
+
+    #[test]
+    fn remaining_time_is_zero_after_the_round_end() {
+        let mut state = game_with(&["a", "b"], &["cat"]);
+        state.choose_word("cat");
+        // as if the turn had started long before its round duration
+        state.round_end_time = get_time_now() - 1000;
+        assert_eq!(state.remaining_time(), 0);
+        assert!(state.is_time_up());
+        assert_eq!(state.elapsed_time(), state.settings.round_duration);
+    }
+
+    #[test]
+    fn turn_passes_on_when_drawers_leave() {
+        let mut state = game_with(&["a", "b", "c"], &["cat", "dog", "fish"]);
+        let a = Username::from("a".to_string());
+        let b = Username::from("b".to_string());
+        assert!(state.is_drawing(&a));
+        state.remove_user(&a);
+        assert!(state.is_drawing(&b));
+        assert!(!state.player_states.contains_key(&a));
+        state.remove_user(&b);
+        assert!(state.is_drawing(&Username::from("c".to_string())));
+        assert_eq!(state.player_states.len(), 1);
+        assert!(!state.remaining_users.contains(&a) && !state.remaining_users.contains(&b));
+    }
+
+    #[test]
+    fn last_player_can_leave() {
+        let mut state = game_with(&["a", "b"], &["cat"]);
+        let a = Username::from("a".to_string());
+        state.remove_user(&Username::from("b".to_string()));
+        state.remove_user(&a);
+        assert!(state.player_states.is_empty());
+        assert!(state.remaining_users.is_empty());
+        // without anyone to draw, the turn stays where it was
+        assert_eq!(state.next_turn(), &a);
+    }
+
+    #[test]
+    fn recent_words_are_not_offered_again() {
+        let words = ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let mut state = game_with(&["x", "y"], &words);
+        state.settings.turn_break = Duration::from_secs(0);
+        state.settings.total_rounds = 100;
+        let mut drawn = Vec::new();
+        for _ in 0..30 {
+            assert!(state.choose_first_word());
+            drawn.push(state.current_word().to_string());
+            state.next_turn();
+        }
+        // whatever order the words were shuffled in, every word is drawn once before any repeats
+        for turns in drawn.windows(words.len()) {
+            let distinct = turns.iter().collect::<HashSet<_>>();
+            assert_eq!(distinct.len(), words.len(), "drawn: {:?}", drawn);
+        }
+    }
+
+    #[test]
+    fn score_decays_over_the_turn() {
+        let settings = settings_with(ScoringSettings::default());
+        assert_eq!(calculate_score_increase(100, &settings), 100);
+        assert_eq!(calculate_score_increase(50, &settings), 55);
+        assert_eq!(calculate_score_increase(0, &settings), 10);
+    }
+
+    #[test]
+    fn score_follows_the_scoring_settings() {
+        let settings = settings_with(ScoringSettings {
+            guess_points: 200,
+            guess_points_decay: 0.5,
+            ..ScoringSettings::default()
+        });
+        assert_eq!(calculate_score_increase(100, &settings), 200);
+        assert_eq!(calculate_score_increase(0, &settings), 100);
+    }
+
+    #[test]
+    fn streak_bonus_is_capped() {
+        let settings = settings_with(ScoringSettings::default());
+        let mut player = PlayerState::default();
+        let points = (0..8)
+            .map(|_| {
+                player.on_solve(100, &settings);
+                player.last_points
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(points, vec![100, 110, 120, 130, 140, 150, 150, 150]);
+        assert_eq!(player.score, points.iter().sum::<u32>());
+        assert_eq!(player.streak, 8);
+    }
+
+    #[test]
+    fn huge_scoring_settings_do_not_overflow() {
+        let settings = settings_with(ScoringSettings {
+            guess_points: u32::MAX,
+            streak_bonus_percent: u32::MAX,
+            max_streak_bonus_percent: u32::MAX,
+            ..ScoringSettings::default()
+        });
+        let mut player = PlayerState {
+            streak: 5,
+            ..PlayerState::default()
+        };
+        player.on_solve(100, &settings);
+        assert_eq!(player.last_points, u32::MAX);
+        player.on_solve(100, &settings);
+        assert_eq!(player.score, u32::MAX);
+    }
 }
@@ -0,0 +1,66 @@
+use super::server::GameState;
+use crate::{
+    data::{CanvasOp, RoomId, TimedMessage},
+    message::PROTOCOL_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+/// what survives of a room when the server restarts. Sessions can't be restored,
+/// players get their score back by rejoining with the same username
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub canvas_ops: Vec<CanvasOp>,
+    pub dimensions: (usize, usize),
+    pub game_state: GameState,
+    /// the latest chat messages, oldest first
+    pub chat_history: Vec<TimedMessage>,
+}
+
+/// every room of the server, written to the resume file on shutdown
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// the PROTOCOL_VERSION of the server that wrote the file. The game state
+    /// changes shape with the protocol, so files of other versions aren't restored
+    pub version: u8,
+    pub rooms: HashMap<RoomId, RoomSnapshot>,
+}
+
+/// just the version of a snapshot, to check it before parsing the rest
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u8,
+}
+
+impl Snapshot {
+    pub fn new(rooms: HashMap<RoomId, RoomSnapshot>) -> Self {
+        Snapshot {
+            version: PROTOCOL_VERSION,
+            rooms,
+        }
+    }
+
+    /// reads the snapshot from the given JSON file. Returns None if the file doesn't exist
+    /// or was written by a server with a different protocol version
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)?;
+        let SnapshotVersion { version } = serde_json::from_str(&content)?;
+        if version != PROTOCOL_VERSION {
+            tracing::warn!(
+                "not resuming from {}: it was saved with protocol version {}, this server uses {}",
+                path.display(),
+                version,
+                PROTOCOL_VERSION
+            );
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+    }
+}
@@ -0,0 +1,65 @@
+use super::skribbl::SkribblState;
+use crate::data::Username;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
+
+/// what a player achieved over all the games they played on this server
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub games_played: u32,
+    pub total_points: u64,
+    pub words_guessed: u32,
+}
+
+/// the stats of every player, by username. Without a file they only last as long as the server runs
+#[derive(Debug, Default)]
+pub struct StatsStore {
+    path: Option<PathBuf>,
+    stats: HashMap<Username, PlayerStats>,
+    /// whether something changed since the stats were last written to the file
+    dirty: bool,
+}
+
+impl StatsStore {
+    /// reads the stats from the given JSON file. A file that doesn't exist yet counts as empty
+    pub fn load(path: Option<PathBuf>) -> std::io::Result<Self> {
+        let stats = match &path {
+            Some(path) if path.exists() => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            _ => HashMap::new(),
+        };
+        Ok(StatsStore {
+            path,
+            stats,
+            dirty: false,
+        })
+    }
+
+    /// adds what every player got in the turn that is about to end
+    pub fn record_turn(&mut self, state: &SkribblState) {
+        for (username, player) in &state.player_states {
+            let stats = self.stats.entry(username.clone()).or_default();
+            stats.total_points += player.last_points as u64;
+            if player.has_solved {
+                stats.words_guessed += 1;
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// counts the game that just ended for everyone still playing it
+    pub fn record_game(&mut self, state: &SkribblState) {
+        for username in state.player_states.keys() {
+            self.stats.entry(username.clone()).or_default().games_played += 1;
+        }
+        self.dirty = true;
+    }
+
+    /// writes the stats to the file, if there is one and anything changed
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(path) = self.path.as_ref().filter(|_| self.dirty) {
+            std::fs::write(path, serde_json::to_string_pretty(&self.stats)?)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}